@@ -0,0 +1,393 @@
+use std::{io::Write, path::PathBuf};
+
+use clap::Parser;
+use rusqlite::Connection;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "SQLite-VSS HTTP server with a Qdrant-compatible API")]
+pub struct Cli {
+    /// Path to a TOML config file.
+    #[arg(short, long, env = "VSS_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    #[arg(long, env = "VSS_DB_PATH")]
+    pub db_path: Option<String>,
+
+    #[arg(long, env = "LISTEN_ADDR")]
+    pub listen_addr: Option<String>,
+
+    /// Maximum request body size in bytes.
+    #[arg(long, env = "VSS_BODY_LIMIT")]
+    pub body_limit: Option<usize>,
+
+    /// Seconds to wait for in-flight requests after SIGTERM/SIGINT.
+    #[arg(long, env = "VSS_SHUTDOWN_TIMEOUT")]
+    pub shutdown_timeout: Option<u64>,
+
+    #[arg(long, env = "VSS_JOURNAL_MODE")]
+    pub journal_mode: Option<String>,
+
+    #[arg(long, env = "VSS_CACHE_SIZE", allow_negative_numbers = true)]
+    pub cache_size: Option<i64>,
+
+    #[arg(long, env = "VSS_MMAP_SIZE")]
+    pub mmap_size: Option<i64>,
+
+    #[arg(long, env = "VSS_WORKER_THREADS")]
+    pub worker_threads: Option<usize>,
+
+    #[arg(long, env = "VSS_BLOCKING_THREADS")]
+    pub blocking_threads: Option<usize>,
+
+    #[arg(long, env = "VSS_API_KEY", hide_env_values = true)]
+    pub api_key: Option<String>,
+
+    #[arg(long, env = "VSS_READ_ONLY_API_KEY", hide_env_values = true)]
+    pub read_only_api_key: Option<String>,
+
+    #[arg(long, env = "VSS_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub service: ServiceConfig,
+    pub storage: StorageConfig,
+    pub pool: PoolConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServiceConfig {
+    pub listen_addr: String,
+    /// `None` leaves the request body size unlimited.
+    pub body_limit: Option<usize>,
+    /// Seconds to drain in-flight requests on shutdown, and again to apply
+    /// queued writes and checkpoint.
+    pub shutdown_timeout: u64,
+    pub api_key: Option<String>,
+    pub read_only_api_key: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub path: String,
+    pub pragmas: PragmaConfig,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PragmaConfig {
+    pub journal_mode: String,
+    pub cache_size: i64,
+    pub mmap_size: i64,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolConfig {
+    /// Tokio worker threads, defaults to the number of CPUs.
+    pub worker_threads: Option<usize>,
+    /// Upper bound for threads running blocking SQLite work.
+    pub blocking_threads: usize,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: LogFormat,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: "0.0.0.0:6333".to_string(),
+            body_limit: None,
+            shutdown_timeout: 30,
+            api_key: None,
+            read_only_api_key: None,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            path: "store.vss.sqlite".to_string(),
+            pragmas: PragmaConfig::default(),
+        }
+    }
+}
+
+impl Default for PragmaConfig {
+    fn default() -> Self {
+        Self {
+            journal_mode: "wal".to_string(),
+            cache_size: -2000,
+            mmap_size: 0,
+        }
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            worker_threads: None,
+            blocking_threads: 512,
+        }
+    }
+}
+
+const JOURNAL_MODES: [&str; 6] = ["delete", "truncate", "persist", "memory", "wal", "off"];
+
+impl Config {
+    /// Builds the effective config: defaults, then the config file, then
+    /// environment variables and CLI flags (CLI wins over env).
+    pub fn load(cli: Cli) -> anyhow::Result<Self> {
+        let mut config = match &cli.config {
+            Some(path) => {
+                let text = std::fs::read_to_string(path).map_err(|e| {
+                    anyhow::anyhow!("failed to read config file {}: {}", path.display(), e)
+                })?;
+                Self::from_toml(&text)
+                    .map_err(|e| anyhow::anyhow!("invalid config file {}: {}", path.display(), e))?
+            }
+            None => Self::default(),
+        };
+        config.merge_cli(cli);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    fn merge_cli(&mut self, cli: Cli) {
+        if let Some(v) = cli.db_path {
+            self.storage.path = v;
+        }
+        if let Some(v) = cli.listen_addr {
+            self.service.listen_addr = v;
+        }
+        if let Some(v) = cli.body_limit {
+            self.service.body_limit = Some(v);
+        }
+        if let Some(v) = cli.shutdown_timeout {
+            self.service.shutdown_timeout = v;
+        }
+        if let Some(v) = cli.journal_mode {
+            self.storage.pragmas.journal_mode = v;
+        }
+        if let Some(v) = cli.cache_size {
+            self.storage.pragmas.cache_size = v;
+        }
+        if let Some(v) = cli.mmap_size {
+            self.storage.pragmas.mmap_size = v;
+        }
+        if let Some(v) = cli.worker_threads {
+            self.pool.worker_threads = Some(v);
+        }
+        if let Some(v) = cli.blocking_threads {
+            self.pool.blocking_threads = v;
+        }
+        if let Some(v) = cli.api_key {
+            self.service.api_key = Some(v);
+        }
+        if let Some(v) = cli.read_only_api_key {
+            self.service.read_only_api_key = Some(v);
+        }
+        if let Some(v) = cli.log_format {
+            self.log.format = v;
+        }
+    }
+
+    /// Reports every problem at once instead of stopping at the first one.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = vec![];
+
+        // Only the syntax is checked, resolving host names is left to
+        // `TcpListener::bind`.
+        if let Err(e) = check_listen_addr(&self.service.listen_addr) {
+            errors.push(format!(
+                "service.listen_addr: `{}` is not a valid socket address: {}",
+                self.service.listen_addr, e
+            ));
+        }
+        if self.service.body_limit == Some(0) {
+            errors.push("service.body_limit: must be greater than 0".to_string());
+        }
+        if self.service.api_key.as_deref() == Some("") {
+            errors.push("service.api_key: must not be empty".to_string());
+        }
+        if self.service.read_only_api_key.as_deref() == Some("") {
+            errors.push("service.read_only_api_key: must not be empty".to_string());
+        }
+        if self.service.read_only_api_key.is_some()
+            && self.service.read_only_api_key == self.service.api_key
+        {
+            errors.push("service.read_only_api_key: must differ from service.api_key".to_string());
+        }
+        if self.storage.path.is_empty() {
+            errors.push("storage.path: must not be empty".to_string());
+        }
+        let journal_mode = self.storage.pragmas.journal_mode.to_lowercase();
+        if !JOURNAL_MODES.contains(&journal_mode.as_str()) {
+            errors.push(format!(
+                "storage.pragmas.journal_mode: `{}` is not one of {}",
+                self.storage.pragmas.journal_mode,
+                JOURNAL_MODES.join(", ")
+            ));
+        }
+        if self.storage.pragmas.cache_size == 0 {
+            errors.push("storage.pragmas.cache_size: must not be 0".to_string());
+        }
+        if self.storage.pragmas.mmap_size < 0 {
+            errors.push("storage.pragmas.mmap_size: must not be negative".to_string());
+        }
+        if self.pool.worker_threads == Some(0) {
+            errors.push("pool.worker_threads: must be greater than 0".to_string());
+        }
+        if self.pool.blocking_threads == 0 {
+            errors.push("pool.blocking_threads: must be greater than 0".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!("invalid configuration:\n  {}", errors.join("\n  "))
+        }
+    }
+}
+
+impl PragmaConfig {
+    pub fn apply(&self, conn: &Connection) -> rusqlite::Result<()> {
+        let journal_mode: String = conn.pragma_update_and_check(
+            None,
+            "journal_mode",
+            self.journal_mode.to_lowercase(),
+            |row| row.get(0),
+        )?;
+        if !journal_mode.eq_ignore_ascii_case(&self.journal_mode) {
+            log::warn!(
+                "journal_mode {} requested but SQLite is using {}",
+                self.journal_mode,
+                journal_mode
+            );
+        }
+        conn.pragma_update(None, "cache_size", self.cache_size)?;
+        conn.pragma_update(None, "mmap_size", self.mmap_size)?;
+        Ok(())
+    }
+}
+
+pub fn init_logger(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": buf.timestamp().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+    builder.init();
+}
+
+/// Checks that `addr` is `host:port`, where an IPv6 host is bracketed.
+fn check_listen_addr(addr: &str) -> Result<(), String> {
+    let (host, port) = addr
+        .rsplit_once(':')
+        .ok_or_else(|| "expected host:port".to_string())?;
+    if host.is_empty() {
+        return Err("the host is empty".to_string());
+    }
+    if host.contains(':') && !(host.starts_with('[') && host.ends_with(']')) {
+        return Err("IPv6 hosts must be in brackets".to_string());
+    }
+    port.parse::<u16>()
+        .map_err(|_| format!("`{}` is not a port number", port))?;
+    Ok(())
+}
+
+#[test]
+fn test_config_from_toml() {
+    let config = Config::from_toml(
+        r#"
+        [service]
+        listen_addr = "127.0.0.1:7000"
+        api_key = "secret"
+
+        [storage]
+        path = "/data/vss.sqlite"
+        pragmas = { journal_mode = "delete", mmap_size = 268435456 }
+
+        [log]
+        format = "json"
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+    assert_eq!(config.service.listen_addr, "127.0.0.1:7000");
+    assert_eq!(config.storage.path, "/data/vss.sqlite");
+    assert_eq!(config.storage.pragmas.journal_mode, "delete");
+    assert_eq!(config.storage.pragmas.cache_size, -2000);
+    assert_eq!(config.log.format, LogFormat::Json);
+
+    assert!(Config::from_toml("[service]\nlisten = \"x\"").is_err());
+}
+
+#[test]
+fn test_config_validate() {
+    let mut config = Config::default();
+    config.service.listen_addr = "localhost".to_string();
+    config.storage.pragmas.journal_mode = "fast".to_string();
+    config.pool.blocking_threads = 0;
+    let err = config.validate().unwrap_err().to_string();
+    assert!(err.contains("service.listen_addr"));
+    assert!(err.contains("storage.pragmas.journal_mode"));
+    assert!(err.contains("pool.blocking_threads"));
+
+    let mut config = Config::default();
+    for addr in [":6333", "localhost:99999", "localhost:port", "::1:6333"] {
+        config.service.listen_addr = addr.to_string();
+        assert!(config.validate().is_err(), "{}", addr);
+    }
+
+    for addr in ["localhost:6333", "0.0.0.0:6333", "[::1]:6333"] {
+        config.service.listen_addr = addr.to_string();
+        config.validate().unwrap();
+    }
+}
+
+#[test]
+fn test_config_cli_overrides_file() {
+    let cli = Cli::parse_from([
+        "rusqlite-vss",
+        "--db-path",
+        "cli.sqlite",
+        "--cache-size",
+        "-4000",
+        "--log-format",
+        "json",
+    ]);
+    let mut config = Config::from_toml("[storage]\npath = \"file.sqlite\"").unwrap();
+    config.merge_cli(cli);
+    assert_eq!(config.storage.path, "cli.sqlite");
+    assert_eq!(config.storage.pragmas.cache_size, -4000);
+    assert_eq!(config.log.format, LogFormat::Json);
+}
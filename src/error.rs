@@ -0,0 +1,87 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    CollectionNotFound(String),
+    CollectionExists(String),
+    AliasNotFound(String),
+    JobNotFound(u64),
+    PointNotFound(u64),
+    DimensionMismatch {
+        expected: usize,
+        got: usize,
+    },
+    InvalidFilter(String),
+    InvalidArgument(String),
+    /// The collection's index has to be trained before it can be searched.
+    IndexNotTrained(String),
+    /// A stored vector blob whose length is not a multiple of 4 bytes.
+    InvalidVectorBlob {
+        len: usize,
+    },
+    Storage(rusqlite::Error),
+    Serialization(serde_json::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::CollectionNotFound(name) => {
+                write!(f, "Not found: Collection `{}` doesn't exist!", name)
+            }
+            Error::CollectionExists(name) => {
+                write!(f, "Wrong input: Collection `{}` already exists!", name)
+            }
+            Error::AliasNotFound(name) => {
+                write!(f, "Not found: Alias `{}` doesn't exist!", name)
+            }
+            Error::JobNotFound(id) => write!(f, "Not found: Job {} doesn't exist!", id),
+            Error::PointNotFound(id) => {
+                write!(f, "Not found: Point with id {} does not exists", id)
+            }
+            Error::DimensionMismatch { expected, got } => write!(
+                f,
+                "Wrong input: Vector dimension error: expected dim: {}, got {}",
+                expected, got
+            ),
+            Error::InvalidFilter(msg) => write!(f, "Bad request: invalid filter: {}", msg),
+            Error::InvalidArgument(msg) => write!(f, "Wrong input: {}", msg),
+            Error::IndexNotTrained(name) => write!(
+                f,
+                "Bad request: Index of collection `{}` is not trained yet",
+                name
+            ),
+            Error::InvalidVectorBlob { len } => write!(
+                f,
+                "Service internal error: stored vector is {} bytes, not a multiple of 4",
+                len
+            ),
+            Error::Storage(e) => write!(f, "Service internal error: {}", e),
+            Error::Serialization(e) => write!(f, "Service internal error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Storage(e) => Some(e),
+            Error::Serialization(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Storage(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Serialization(e)
+    }
+}
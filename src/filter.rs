@@ -0,0 +1,330 @@
+//! Compiles payload filters into SQL over the `vss_{name}_payload` table.
+
+use rusqlite::types::Value;
+use serde_json::Value as JsonValue;
+
+use crate::{
+    geo,
+    types::{Condition, FieldCondition, Filter, Match, Range},
+    Error, Result,
+};
+
+/// A boolean SQL expression over the payload table aliased as `p`, with
+/// `?` placeholders for `params` in order.
+#[derive(Debug, Default)]
+pub struct SqlFilter {
+    pub sql: String,
+    pub params: Vec<Value>,
+}
+
+/// Names the R*Tree table indexing a geo payload key, if there is one.
+pub type GeoIndexes<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+/// Geo conditions on keys with an index are narrowed down with the R*Tree
+/// before the exact check.
+pub fn compile(filter: &Filter, geo_indexes: GeoIndexes) -> Result<SqlFilter> {
+    let mut compiled = SqlFilter::default();
+    compiled.sql = compile_filter(filter, geo_indexes, &mut compiled.params)?;
+    Ok(compiled)
+}
+
+fn compile_filter(filter: &Filter, geo: GeoIndexes, params: &mut Vec<Value>) -> Result<String> {
+    let mut clauses = vec![];
+    for condition in &filter.must {
+        clauses.push(compile_condition(condition, geo, params)?);
+    }
+    if !filter.should.is_empty() {
+        let should = filter
+            .should
+            .iter()
+            .map(|c| compile_condition(c, geo, params))
+            .collect::<Result<Vec<_>>>()?;
+        clauses.push(format!("({})", should.join(" OR ")));
+    }
+    for condition in &filter.must_not {
+        clauses.push(format!(
+            "NOT {}",
+            compile_condition(condition, geo, params)?
+        ));
+    }
+    if clauses.is_empty() {
+        return Ok("1".to_string());
+    }
+    Ok(format!("({})", clauses.join(" AND ")))
+}
+
+fn compile_condition(
+    condition: &Condition,
+    geo: GeoIndexes,
+    params: &mut Vec<Value>,
+) -> Result<String> {
+    match condition {
+        Condition::HasId(has_id) => {
+            if has_id.has_id.is_empty() {
+                return Ok("0".to_string());
+            }
+            let mut ids = Vec::with_capacity(has_id.has_id.len());
+            for id in &has_id.has_id {
+                let id = i64::try_from(*id).map_err(|_| {
+                    Error::InvalidFilter(format!("point id {} is out of range", id))
+                })?;
+                ids.push(id.to_string());
+            }
+            Ok(format!("p.rowid IN ({})", ids.join(",")))
+        }
+        Condition::Field(field) => compile_field(field, geo, params),
+        Condition::Filter(filter) => compile_filter(filter, geo, params),
+    }
+}
+
+fn compile_field(
+    field: &FieldCondition,
+    geo: GeoIndexes,
+    params: &mut Vec<Value>,
+) -> Result<String> {
+    let path = json_path(&field.key)?;
+    let set = [
+        field.r#match.is_some(),
+        field.range.is_some(),
+        field.geo_radius.is_some(),
+        field.geo_bounding_box.is_some(),
+        field.geo_polygon.is_some(),
+    ];
+    if set.iter().filter(|set| **set).count() != 1 {
+        return Err(one_condition_error(&field.key));
+    }
+    if let Some(m) = &field.r#match {
+        return compile_match(&field.key, &path, m, params);
+    }
+    if let Some(range) = &field.range {
+        return compile_range(&field.key, &path, range, params);
+    }
+    compile_geo(field, geo, params)
+}
+
+fn one_condition_error(key: &str) -> Error {
+    Error::InvalidFilter(format!(
+        "condition on `{}` needs exactly one of `match`, `range`, `geo_radius`, `geo_bounding_box` and `geo_polygon`",
+        key
+    ))
+}
+
+/// Locations are `{"lat": .., "lon": ..}` objects. Conditions on a key with
+/// a geo index first select the R*Tree entries overlapping the condition's
+/// bounds, then check the exact shape.
+fn compile_geo(field: &FieldCondition, geo: GeoIndexes, params: &mut Vec<Value>) -> Result<String> {
+    let lat = format!(
+        "json_extract(p.payload, {})",
+        json_path(&format!("{}.lat", field.key))?
+    );
+    let lon = format!(
+        "json_extract(p.payload, {})",
+        json_path(&format!("{}.lon", field.key))?
+    );
+    let mut shape_params = vec![];
+    let (shape, bounds) = if let Some(radius) = &field.geo_radius {
+        geo::check_point(&radius.center)?;
+        if radius.radius.is_nan() || radius.radius < 0.0 {
+            return Err(Error::InvalidFilter(format!(
+                "geo_radius on `{}` needs a radius of at least 0 meters",
+                field.key
+            )));
+        }
+        shape_params.extend([
+            Value::Real(radius.center.lat),
+            Value::Real(radius.center.lon),
+            Value::Real(radius.radius),
+        ]);
+        (
+            format!("geo_distance({}, {}, ?, ?) <= ?", lat, lon),
+            geo::radius_bounds(radius),
+        )
+    } else if let Some(bounding_box) = &field.geo_bounding_box {
+        let (top_left, bottom_right) = (&bounding_box.top_left, &bounding_box.bottom_right);
+        geo::check_point(top_left)?;
+        geo::check_point(bottom_right)?;
+        shape_params.extend([
+            Value::Real(bottom_right.lat),
+            Value::Real(top_left.lat),
+            Value::Real(top_left.lon),
+            Value::Real(bottom_right.lon),
+        ]);
+        let op = match top_left.lon > bottom_right.lon {
+            true => "OR",
+            false => "AND",
+        };
+        (
+            format!(
+                "{} BETWEEN ? AND ? AND ({} >= ? {} {} <= ?)",
+                lat, lon, op, lon
+            ),
+            geo::bounding_box_bounds(bounding_box),
+        )
+    } else if let Some(polygon) = &field.geo_polygon {
+        for ring in std::iter::once(&polygon.exterior).chain(&polygon.interiors) {
+            if ring.points.len() < 3 {
+                return Err(Error::InvalidFilter(format!(
+                    "geo_polygon on `{}` needs rings of at least 3 points",
+                    field.key
+                )));
+            }
+            for point in &ring.points {
+                geo::check_point(point)?;
+            }
+        }
+        shape_params.push(Value::Text(serde_json::to_string(polygon)?));
+        (
+            format!("geo_in_polygon({}, {}, ?)", lat, lon),
+            Some(geo::polygon_bounds(polygon)),
+        )
+    } else {
+        return Err(one_condition_error(&field.key));
+    };
+
+    let shape = match (geo(&field.key), bounds) {
+        (Some(index), Some((min_lat, max_lat, min_lon, max_lon))) => {
+            params.extend([
+                Value::Real(min_lat),
+                Value::Real(max_lat),
+                Value::Real(min_lon),
+                Value::Real(max_lon),
+            ]);
+            format!(
+                "p.rowid IN (SELECT id FROM {} WHERE max_lat >= ? AND min_lat <= ? AND max_lon >= ? AND min_lon <= ?) AND {}",
+                index, shape
+            )
+        }
+        _ => shape,
+    };
+    params.extend(shape_params);
+    Ok(format!("({})", shape))
+}
+
+/// Paths are inlined rather than bound so the expression is identical to
+/// the one a payload index is built on, letting SQLite use the index.
+pub fn json_path(key: &str) -> Result<String> {
+    let valid = !key.is_empty()
+        && key.split('.').all(|part| {
+            !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+    if !valid {
+        return Err(Error::InvalidFilter(format!(
+            "`{}` is not a valid payload key",
+            key
+        )));
+    }
+    Ok(format!("'$.{}'", key))
+}
+
+fn compile_match(key: &str, path: &str, m: &Match, params: &mut Vec<Value>) -> Result<String> {
+    let extract = format!("json_extract(p.payload, {})", path);
+    match m {
+        Match::Value { value } => {
+            params.push(sql_value(key, value)?);
+            Ok(format!("{} = ?", extract))
+        }
+        Match::Any { any } | Match::Except { except: any } => {
+            let negate = matches!(m, Match::Except { .. });
+            if any.is_empty() {
+                return Ok(if negate { "1" } else { "0" }.to_string());
+            }
+            for value in any {
+                params.push(sql_value(key, value)?);
+            }
+            let placeholders = vec!["?"; any.len()].join(",");
+            if negate {
+                Ok(format!(
+                    "({} IS NOT NULL AND {} NOT IN ({}))",
+                    extract, extract, placeholders
+                ))
+            } else {
+                Ok(format!("{} IN ({})", extract, placeholders))
+            }
+        }
+    }
+}
+
+fn compile_range(key: &str, path: &str, range: &Range, params: &mut Vec<Value>) -> Result<String> {
+    let extract = format!("json_extract(p.payload, {})", path);
+    // Without the type check SQLite would order every string above any
+    // number and match it.
+    let mut clauses = vec![format!(
+        "json_type(p.payload, {}) IN ('integer', 'real')",
+        path
+    )];
+    for (op, bound) in [
+        (">", range.gt),
+        (">=", range.gte),
+        ("<", range.lt),
+        ("<=", range.lte),
+    ] {
+        if let Some(bound) = bound {
+            if bound.is_nan() {
+                return Err(Error::InvalidFilter(format!(
+                    "range bound on `{}` is not a number",
+                    key
+                )));
+            }
+            params.push(Value::Real(bound));
+            clauses.push(format!("{} {} ?", extract, op));
+        }
+    }
+    if clauses.len() == 1 {
+        return Err(Error::InvalidFilter(format!(
+            "range on `{}` needs at least one of gt, gte, lt, lte",
+            key
+        )));
+    }
+    Ok(format!("({})", clauses.join(" AND ")))
+}
+
+/// `json_extract` returns JSON booleans as integers and numbers as SQL
+/// numbers, so match values are bound the same way.
+fn sql_value(key: &str, value: &JsonValue) -> Result<Value> {
+    match value {
+        JsonValue::String(s) => Ok(Value::Text(s.clone())),
+        JsonValue::Bool(b) => Ok(Value::Integer(*b as i64)),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) => Ok(Value::Integer(i)),
+            None => Ok(Value::Real(n.as_f64().unwrap_or(f64::NAN))),
+        },
+        _ => Err(Error::InvalidFilter(format!(
+            "match on `{}` must be a string, number or boolean",
+            key
+        ))),
+    }
+}
+
+#[test]
+fn test_compile_filter() {
+    let filter: Filter = serde_json::from_value(serde_json::json!({
+        "must": [
+            {"key": "city", "match": {"value": "Berlin"}},
+            {"key": "stats.count", "range": {"gte": 1, "lt": 10}}
+        ],
+        "should": [{"has_id": [1, 2]}, {"key": "open", "match": {"value": true}}],
+        "must_not": [{"must": [{"key": "tag", "match": {"any": ["a", "b"]}}]}]
+    }))
+    .unwrap();
+    let compiled = compile(&filter, &|_| None).unwrap();
+    assert_eq!(
+        compiled.sql,
+        "(json_extract(p.payload, '$.city') = ? AND (json_type(p.payload, '$.stats.count') IN ('integer', 'real') AND json_extract(p.payload, '$.stats.count') >= ? AND json_extract(p.payload, '$.stats.count') < ?) AND (p.rowid IN (1,2) OR json_extract(p.payload, '$.open') = ?) AND NOT (json_extract(p.payload, '$.tag') IN (?,?)))"
+    );
+    assert_eq!(compiled.params.len(), 6);
+    assert_eq!(compiled.params[3], Value::Integer(1));
+
+    assert_eq!(compile(&Filter::default(), &|_| None).unwrap().sql, "1");
+
+    for bad in [
+        serde_json::json!({"must": [{"key": "a'b", "match": {"value": 1}}]}),
+        serde_json::json!({"must": [{"key": "a", "range": {}}]}),
+        serde_json::json!({"must": [{"key": "a", "match": {"value": [1]}}]}),
+    ] {
+        let filter: Filter = serde_json::from_value(bad).unwrap();
+        assert!(matches!(
+            compile(&filter, &|_| None),
+            Err(Error::InvalidFilter(_))
+        ));
+    }
+}
@@ -0,0 +1,209 @@
+//! Geo helpers for payload filters: SQL functions registered on every
+//! connection and the bounds used to query R*Tree geo indexes.
+
+use rusqlite::{
+    functions::{Context, FunctionFlags},
+    types::ValueRef,
+    Connection,
+};
+
+use crate::{
+    types::{GeoBoundingBox, GeoPoint, GeoPolygon, GeoRadius},
+    Error, Result,
+};
+
+/// Mean Earth radius in meters, as used by Qdrant.
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// `(min_lat, max_lat, min_lon, max_lon)` in degrees.
+pub type Bounds = (f64, f64, f64, f64);
+
+/// Registers `geo_distance(lat1, lon1, lat2, lon2)`, in meters, and
+/// `geo_in_polygon(lat, lon, polygon_json)`. Both return NULL when a
+/// coordinate is not a number.
+pub fn register_functions(conn: &Connection) -> Result<()> {
+    let flags = || FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    conn.create_scalar_function("geo_distance", 4, flags(), |ctx| {
+        let coordinates = (0..4).map(|i| number(ctx, i)).collect::<Option<Vec<_>>>();
+        Ok(coordinates.map(|c| {
+            distance(
+                &GeoPoint {
+                    lat: c[0],
+                    lon: c[1],
+                },
+                &GeoPoint {
+                    lat: c[2],
+                    lon: c[3],
+                },
+            )
+        }))
+    })?;
+    conn.create_scalar_function("geo_in_polygon", 3, flags(), |ctx| {
+        // The polygon is the same for every row, parse it once per statement.
+        let polygon = ctx.get_or_create_aux(2, |value| {
+            serde_json::from_str::<GeoPolygon>(value.as_str()?)
+                .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+        })?;
+        let (Some(lat), Some(lon)) = (number(ctx, 0), number(ctx, 1)) else {
+            return Ok(None);
+        };
+        Ok(Some(contains(&polygon, &GeoPoint { lat, lon })))
+    })?;
+    Ok(())
+}
+
+fn number(ctx: &Context, i: usize) -> Option<f64> {
+    match ctx.get_raw(i) {
+        ValueRef::Integer(i) => Some(i as f64),
+        ValueRef::Real(r) => Some(r),
+        _ => None,
+    }
+}
+
+/// Great-circle distance in meters, by the haversine formula.
+pub fn distance(a: &GeoPoint, b: &GeoPoint) -> f64 {
+    let (lat1, lat2) = (a.lat.to_radians(), b.lat.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (b.lon - a.lon).to_radians();
+    let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * h.sqrt().min(1.0).asin()
+}
+
+/// Ray casting on the lat/lon plane: inside the exterior ring and outside
+/// every interior one.
+pub fn contains(polygon: &GeoPolygon, point: &GeoPoint) -> bool {
+    ring_contains(&polygon.exterior.points, point)
+        && !polygon
+            .interiors
+            .iter()
+            .any(|ring| ring_contains(&ring.points, point))
+}
+
+fn ring_contains(ring: &[GeoPoint], point: &GeoPoint) -> bool {
+    let mut inside = false;
+    let mut j = ring.len().wrapping_sub(1);
+    for (i, a) in ring.iter().enumerate() {
+        let b = &ring[j];
+        if (a.lat > point.lat) != (b.lat > point.lat)
+            && point.lon < (b.lon - a.lon) * (point.lat - a.lat) / (b.lat - a.lat) + a.lon
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+pub fn check_point(point: &GeoPoint) -> Result<()> {
+    if !(-90.0..=90.0).contains(&point.lat) || !(-180.0..=180.0).contains(&point.lon) {
+        return Err(Error::InvalidFilter(format!(
+            "({}, {}) is not a valid location, lat must be within ±90 and lon within ±180",
+            point.lat, point.lon
+        )));
+    }
+    Ok(())
+}
+
+/// The box around the circle, or `None` when it covers a pole or crosses
+/// the antimeridian.
+pub fn radius_bounds(radius: &GeoRadius) -> Option<Bounds> {
+    let angle = radius.radius / EARTH_RADIUS;
+    let dlat = angle.to_degrees();
+    let lat = radius.center.lat;
+    if lat - dlat <= -90.0 || lat + dlat >= 90.0 {
+        return None;
+    }
+    let dlon = (angle.sin() / lat.to_radians().cos())
+        .min(1.0)
+        .asin()
+        .to_degrees();
+    let lon = radius.center.lon;
+    if lon - dlon < -180.0 || lon + dlon > 180.0 {
+        return None;
+    }
+    Some((lat - dlat, lat + dlat, lon - dlon, lon + dlon))
+}
+
+/// `None` when the box crosses the antimeridian.
+pub fn bounding_box_bounds(bounding_box: &GeoBoundingBox) -> Option<Bounds> {
+    let (top_left, bottom_right) = (&bounding_box.top_left, &bounding_box.bottom_right);
+    if top_left.lon > bottom_right.lon {
+        return None;
+    }
+    Some((
+        bottom_right.lat,
+        top_left.lat,
+        top_left.lon,
+        bottom_right.lon,
+    ))
+}
+
+pub fn polygon_bounds(polygon: &GeoPolygon) -> Bounds {
+    polygon.exterior.points.iter().fold(
+        (
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        ),
+        |(min_lat, max_lat, min_lon, max_lon), p| {
+            (
+                min_lat.min(p.lat),
+                max_lat.max(p.lat),
+                min_lon.min(p.lon),
+                max_lon.max(p.lon),
+            )
+        },
+    )
+}
+
+#[test]
+fn test_geo() {
+    use crate::types::GeoLineString;
+
+    let berlin = GeoPoint {
+        lat: 52.5200,
+        lon: 13.4050,
+    };
+    let paris = GeoPoint {
+        lat: 48.8566,
+        lon: 2.3522,
+    };
+    let d = distance(&berlin, &paris);
+    assert!((d - 877_500.0).abs() < 2_000.0, "{}", d);
+
+    let (min_lat, max_lat, min_lon, max_lon) = radius_bounds(&GeoRadius {
+        center: berlin,
+        radius: 10_000.0,
+    })
+    .unwrap();
+    for corner in [(min_lat, berlin.lon), (berlin.lat, max_lon)] {
+        let corner = GeoPoint {
+            lat: corner.0,
+            lon: corner.1,
+        };
+        assert!((distance(&berlin, &corner) - 10_000.0).abs() < 1.0);
+    }
+    assert!(min_lon < berlin.lon && max_lat > berlin.lat);
+
+    let ring = |points: &[(f64, f64)]| GeoLineString {
+        points: points
+            .iter()
+            .map(|&(lat, lon)| GeoPoint { lat, lon })
+            .collect(),
+    };
+    let polygon = GeoPolygon {
+        exterior: ring(&[(0.0, 0.0), (0.0, 10.0), (10.0, 10.0), (10.0, 0.0)]),
+        interiors: vec![ring(&[(4.0, 4.0), (4.0, 6.0), (6.0, 6.0), (6.0, 4.0)])],
+    };
+    assert!(contains(&polygon, &GeoPoint { lat: 2.0, lon: 2.0 }));
+    assert!(!contains(&polygon, &GeoPoint { lat: 5.0, lon: 5.0 }));
+    assert!(!contains(
+        &polygon,
+        &GeoPoint {
+            lat: 11.0,
+            lon: 5.0
+        }
+    ));
+    assert_eq!(polygon_bounds(&polygon), (0.0, 10.0, 0.0, 10.0));
+}
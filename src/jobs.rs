@@ -0,0 +1,342 @@
+//! Background jobs: long operations persisted in the `jobs` table and moved
+//! forward by [`step`], one short transaction at a time, so that the
+//! connection serves requests in between.
+
+use rusqlite::{params, Connection};
+
+use crate::{
+    store,
+    types::{JobInfo, JobKind, JobStatus},
+    Error, Result,
+};
+
+const ACTIVE: &str = "status IN ('queued', 'running')";
+
+const COLUMNS: &str = "id, kind, collection, status, done, total, error, created_at, updated_at";
+
+/// Creates the `jobs` table and recovers the jobs of a previous process:
+/// an active job resumes if its collection still has a pending rebuild and
+/// is marked failed otherwise. A pending rebuild without a job, e.g. one
+/// started before jobs existed, gets a new one.
+pub fn init_schema(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS jobs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            collection TEXT NOT NULL,
+            status TEXT NOT NULL,
+            done INTEGER NOT NULL DEFAULT 0,
+            total INTEGER NOT NULL DEFAULT 0,
+            error TEXT,
+            created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+            updated_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );
+        CREATE INDEX IF NOT EXISTS jobs_collection ON jobs (collection);
+        "#,
+    )?;
+
+    let pending = store::pending_rebuilds(conn)?;
+    let tx = conn.unchecked_transaction()?;
+    for job in query(&tx, &format!("WHERE {}", ACTIVE), [])? {
+        if pending.contains(&job.collection_name) {
+            set_status(&tx, job.id, JobStatus::Queued, None)?;
+        } else {
+            set_status(
+                &tx,
+                job.id,
+                JobStatus::Failed,
+                Some("interrupted by a restart"),
+            )?;
+        }
+    }
+    for name in pending {
+        let has_job: bool = tx.query_row(
+            &format!(
+                "SELECT EXISTS (SELECT 1 FROM jobs WHERE collection = ?1 AND {})",
+                ACTIVE
+            ),
+            params![name],
+            |row| row.get(0),
+        )?;
+        if !has_job {
+            let config = store::get_collection_config(&tx, &name)?;
+            let kind = match config.rebuild.and_then(|r| r.sample_size) {
+                Some(_) => JobKind::TrainIndex,
+                None => JobKind::RebuildIndex,
+            };
+            create(&tx, kind, &name)?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Queues a job for `collection`, cancelling the one it replaces.
+pub fn create(conn: &Connection, kind: JobKind, collection: &str) -> Result<JobInfo> {
+    conn.execute(
+        &format!(
+            r#"
+            UPDATE jobs SET status = 'cancelled', error = 'superseded by a newer job',
+                updated_at = unixepoch()
+            WHERE collection = ?1 AND {}
+            "#,
+            ACTIVE
+        ),
+        params![collection],
+    )?;
+    let total = store::points_count(conn, collection)?;
+    let id: u64 = conn.query_row(
+        "INSERT INTO jobs (kind, collection, status, total) VALUES (?1, ?2, ?3, ?4) RETURNING id",
+        params![
+            to_text(&kind)?,
+            collection,
+            to_text(&JobStatus::Queued)?,
+            total
+        ],
+        |row| row.get(0),
+    )?;
+    get(conn, id)
+}
+
+pub fn get(conn: &Connection, id: u64) -> Result<JobInfo> {
+    query(conn, "WHERE id = ?1", params![id])?
+        .pop()
+        .ok_or(Error::JobNotFound(id))
+}
+
+/// Every job, newest first.
+pub fn list(conn: &Connection) -> Result<Vec<JobInfo>> {
+    query(conn, "ORDER BY id DESC", [])
+}
+
+/// Ids of the jobs that still have work to do, oldest first.
+pub fn active(conn: &Connection) -> Result<Vec<u64>> {
+    let mut stmt = conn.prepare(&format!("SELECT id FROM jobs WHERE {} ORDER BY id", ACTIVE))?;
+    let ids = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(ids)
+}
+
+/// Stops job `id` and undoes its work, leaving the collection as it was
+/// before the job started.
+pub fn cancel(conn: &Connection, id: u64) -> Result<JobInfo> {
+    let job = get(conn, id)?;
+    if !job.status.is_active() {
+        return Err(Error::InvalidArgument(format!(
+            "job {} is already {}",
+            id,
+            to_text(&job.status)?
+        )));
+    }
+    let tx = conn.unchecked_transaction()?;
+    abort(&tx, &job)?;
+    set_status(&tx, id, JobStatus::Cancelled, None)?;
+    tx.commit()?;
+    get(conn, id)
+}
+
+/// Moves job `id` forward by one transaction of at most `batch_size`
+/// points and returns its new state. A failing step marks the job failed
+/// and undoes its work. Jobs that are no longer active are left as they
+/// are.
+pub fn step(conn: &Connection, id: u64, batch_size: usize) -> Result<JobInfo> {
+    let job = get(conn, id)?;
+    if !job.status.is_active() {
+        return Ok(job);
+    }
+    let tx = conn.unchecked_transaction()?;
+    match advance(&tx, &job, batch_size) {
+        Ok((status, done, total)) => {
+            tx.execute(
+                r#"
+                UPDATE jobs SET status = ?2, done = ?3, total = ?4, updated_at = unixepoch()
+                WHERE id = ?1
+                "#,
+                params![id, to_text(&status)?, done, total],
+            )?;
+            tx.commit()?;
+        }
+        Err(e) => {
+            drop(tx);
+            let tx = conn.unchecked_transaction()?;
+            abort(&tx, &job)?;
+            set_status(&tx, id, JobStatus::Failed, Some(&e.to_string()))?;
+            tx.commit()?;
+        }
+    }
+    get(conn, id)
+}
+
+/// Returns the job's status and progress after one step.
+fn advance(conn: &Connection, job: &JobInfo, batch_size: usize) -> Result<(JobStatus, u64, u64)> {
+    let name = &job.collection_name;
+    let done = match job.kind {
+        JobKind::TrainIndex | JobKind::RebuildIndex => store::rebuild_step(conn, name, batch_size)?,
+    };
+    let total = store::points_count(conn, name)?;
+    if done {
+        return Ok((JobStatus::Completed, total, total));
+    }
+    let copied = store::get_collection_config(conn, name)?
+        .rebuild
+        .map_or(0, |rebuild| rebuild.points_copied);
+    Ok((JobStatus::Running, copied, total))
+}
+
+/// Undoes the work of `job`. A deleted collection has nothing to undo.
+fn abort(conn: &Connection, job: &JobInfo) -> Result<()> {
+    let aborted = match job.kind {
+        JobKind::TrainIndex | JobKind::RebuildIndex => {
+            store::abort_rebuild(conn, &job.collection_name)
+        }
+    };
+    match aborted {
+        Err(Error::CollectionNotFound(_)) => Ok(()),
+        aborted => aborted,
+    }
+}
+
+fn set_status(conn: &Connection, id: u64, status: JobStatus, error: Option<&str>) -> Result<()> {
+    conn.execute(
+        "UPDATE jobs SET status = ?2, error = ?3, updated_at = unixepoch() WHERE id = ?1",
+        params![id, to_text(&status)?, error],
+    )?;
+    Ok(())
+}
+
+fn query<P: rusqlite::Params>(conn: &Connection, clause: &str, params: P) -> Result<Vec<JobInfo>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM jobs {}", COLUMNS, clause))?;
+    let rows = stmt
+        .query_map(params, |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, u64>(4)?,
+                row.get::<_, u64>(5)?,
+                row.get::<_, Option<String>>(6)?,
+                row.get::<_, i64>(7)?,
+                row.get::<_, i64>(8)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    rows.into_iter()
+        .map(
+            |(id, kind, collection_name, status, done, total, error, created_at, updated_at)| {
+                Ok(JobInfo {
+                    id,
+                    kind: from_text(kind)?,
+                    collection_name,
+                    status: from_text(status)?,
+                    done,
+                    total,
+                    error,
+                    created_at,
+                    updated_at,
+                })
+            },
+        )
+        .collect()
+}
+
+/// Kinds and statuses are stored by their serde names.
+fn to_text<T: serde::Serialize>(value: &T) -> Result<String> {
+    match serde_json::to_value(value)? {
+        serde_json::Value::String(text) => Ok(text),
+        other => Ok(other.to_string()),
+    }
+}
+
+fn from_text<T: serde::de::DeserializeOwned>(text: String) -> Result<T> {
+    Ok(serde_json::from_value(serde_json::Value::String(text))?)
+}
+
+#[test]
+fn test_jobs() {
+    use crate::{CollectionUpdate, Point, QuantizationConfig, ScalarQuantization, ScalarType};
+
+    store::init();
+    let conn = Connection::open_in_memory().unwrap();
+    store::init_schema(&conn).unwrap();
+    store::create_collections(&conn, "test_jobs", 2).unwrap();
+    let points = (1..=10)
+        .map(|i| Point {
+            id: i,
+            vector: vec![i as f32, 0.0].into(),
+            payload: None,
+        })
+        .collect::<Vec<_>>();
+    store::add_point(&conn, "test_jobs", &points).unwrap();
+    let update = CollectionUpdate {
+        index_factory: None,
+        quantization_config: Some(QuantizationConfig::Scalar(ScalarQuantization {
+            kind: ScalarType::Int8,
+        })),
+    };
+
+    // A rebuild job trains, copies 4 + 4 + 2 points and swaps.
+    store::update_collection(&conn, "test_jobs", &update).unwrap();
+    let job = create(&conn, JobKind::RebuildIndex, "test_jobs").unwrap();
+    assert_eq!(
+        (job.status, job.done, job.total),
+        (JobStatus::Queued, 0, 10)
+    );
+    assert_eq!(active(&conn).unwrap(), vec![job.id]);
+    let job = step(&conn, job.id, 4).unwrap();
+    assert_eq!((job.status, job.done), (JobStatus::Running, 0));
+    let job = step(&conn, job.id, 4).unwrap();
+    assert_eq!((job.status, job.done), (JobStatus::Running, 4));
+    while step(&conn, job.id, 4).unwrap().status.is_active() {}
+    let job = get(&conn, job.id).unwrap();
+    assert_eq!(
+        (job.status, job.done, job.total),
+        (JobStatus::Completed, 10, 10)
+    );
+    let config = store::get_collection_config(&conn, "test_jobs").unwrap();
+    assert_eq!(config.quantization_config, update.quantization_config);
+    assert!(cancel(&conn, job.id).is_err());
+
+    // A second update supersedes the first, and cancelling undoes it.
+    store::update_collection(&conn, "test_jobs", &CollectionUpdate::default()).unwrap();
+    let first = create(&conn, JobKind::RebuildIndex, "test_jobs").unwrap();
+    step(&conn, first.id, 4).unwrap();
+    store::update_collection(&conn, "test_jobs", &CollectionUpdate::default()).unwrap();
+    let second = create(&conn, JobKind::RebuildIndex, "test_jobs").unwrap();
+    assert_eq!(get(&conn, first.id).unwrap().status, JobStatus::Cancelled);
+    let cancelled = cancel(&conn, second.id).unwrap();
+    assert_eq!(cancelled.status, JobStatus::Cancelled);
+    let config = store::get_collection_config(&conn, "test_jobs").unwrap();
+    assert!(config.rebuild.is_none());
+    assert_eq!(config.quantization_config, update.quantization_config);
+    assert!(active(&conn).unwrap().is_empty());
+
+    // After a restart a running job with a pending rebuild resumes, one
+    // without is failed, and a pending rebuild without a job gets one.
+    store::update_collection(&conn, "test_jobs", &CollectionUpdate::default()).unwrap();
+    let resumed = create(&conn, JobKind::RebuildIndex, "test_jobs").unwrap();
+    step(&conn, resumed.id, 4).unwrap();
+    store::create_collections(&conn, "test_jobs_lost", 2).unwrap();
+    let lost = create(&conn, JobKind::RebuildIndex, "test_jobs_lost").unwrap();
+    init_schema(&conn).unwrap();
+    assert_eq!(get(&conn, resumed.id).unwrap().status, JobStatus::Queued);
+    let lost = get(&conn, lost.id).unwrap();
+    assert_eq!(lost.status, JobStatus::Failed);
+    assert_eq!(lost.error.as_deref(), Some("interrupted by a restart"));
+    conn.execute("DELETE FROM jobs", []).unwrap();
+    init_schema(&conn).unwrap();
+    let jobs = list(&conn).unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(
+        (jobs[0].kind, jobs[0].status),
+        (JobKind::RebuildIndex, JobStatus::Queued)
+    );
+
+    // Deleting the collection fails its job.
+    store::delete_collection(&conn, "test_jobs").unwrap();
+    let failed = step(&conn, jobs[0].id, 4).unwrap();
+    assert_eq!(failed.status, JobStatus::Failed);
+    assert!(matches!(get(&conn, 1000), Err(Error::JobNotFound(1000))));
+}
@@ -0,0 +1,38 @@
+//! Embeddable vector store on top of SQLite and the sqlite-vss extension.
+//!
+//! ```no_run
+//! use rusqlite_vss::{Point, VectorStore};
+//!
+//! let store = VectorStore::open("store.vss.sqlite")?;
+//! let collection = store.create_collection("docs", 4)?;
+//! collection.upsert(&[Point {
+//!     id: 1,
+//!     vector: vec![0.05, 0.61, 0.76, 0.74].into(),
+//!     payload: None,
+//! }])?;
+//! let hits = collection.search(&[0.2, 0.1, 0.9, 0.7], 3)?;
+//! # Ok::<(), rusqlite_vss::Error>(())
+//! ```
+
+pub mod error;
+mod filter;
+mod geo;
+mod jobs;
+mod store;
+pub mod types;
+mod vector_store;
+
+pub use error::{Error, Result};
+pub use types::{
+    AliasDescription, AliasOperation, CollectionConfig, CollectionInfo, CollectionUpdate,
+    Condition, Direction, EvaluateRequest, EvaluationReport, FieldCondition, Filter, Fusion,
+    FusionRequest, GeoBoundingBox, GeoLineString, GeoPoint, GeoPolygon, GeoRadius, GroupsResult,
+    HasIdCondition, IndexRebuild, JobInfo, JobKind, JobStatus, Match, Mmr, MultiVectorComparator,
+    MultiVectorParams, NamedMultiVector, NamedSparseVector, NamedVector, NamedVectorStruct,
+    OrderBy, Payload, PayloadIndexInfo, PayloadSchemaType, Point, PointGroup, PointOperation,
+    PointVectors, QuantizationConfig, QuantizationSearchParams, Range, RebuildInfo,
+    ScalarQuantization, ScalarType, ScoredPoint, ScrollRequest, ScrollResult, SearchGroupsRequest,
+    SearchParams, SearchRequest, SparseSearchRequest, SparseVector, SparseVectorParams,
+    TrainReport, TrainRequest, Vector, VectorParams, VectorStruct, VectorsConfig,
+};
+pub use vector_store::{Collection, VectorStore};
@@ -1,48 +1,142 @@
-use std::sync::Arc;
+use std::{future::IntoFuture, sync::Arc, time::Duration};
 
-use axum::{
-    extract::DefaultBodyLimit,
-    routing::{delete, get, post, put},
-    Router,
-};
+use axum::extract::DefaultBodyLimit;
+use clap::Parser;
+use rusqlite_vss::VectorStore;
 use tokio::sync::Mutex;
 
-pub mod service;
-pub mod store;
-
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    env_logger::init();
-    store::init();
-
-    let addr = std::env::var("LISTEN_ADDR").unwrap_or("0.0.0.0:6333".to_string());
-
-    let db = store::open("store.vss.sqlite")?;
-
-    let app = Router::new()
-        .route("/collections/:name", put(service::create_collections))
-        .route("/collections/:name", get(service::get_collections_info))
-        .route("/collections/:name", delete(service::delete_collection))
-        .route(
-            "/collections/:name/points/:point_id",
-            get(service::get_point),
-        )
-        .route("/collections/:name/points", put(service::add_points))
-        .route(
-            "/collections/:name/points/delete",
-            post(service::delete_points),
-        )
-        .route(
-            "/collections/:name/points/search",
-            post(service::search_points),
-        )
-        .route("/collections/:name/points", post(service::get_points))
-        .layer(DefaultBodyLimit::disable())
-        .with_state(Arc::new(Mutex::new(db)));
-
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    log::info!("Listening on: {}", addr);
-    axum::serve(listener, app).await?;
+mod config;
+mod service;
 
+fn main() -> anyhow::Result<()> {
+    let config = config::Config::load(config::Cli::parse())?;
+    config::init_logger(config.log.format);
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime
+        .enable_all()
+        .max_blocking_threads(config.pool.blocking_threads);
+    if let Some(worker_threads) = config.pool.worker_threads {
+        runtime.worker_threads(worker_threads);
+    }
+    runtime.build()?.block_on(serve(config))
+}
+
+async fn serve(config: config::Config) -> anyhow::Result<()> {
+    let db = VectorStore::open(&config.storage.path)?;
+    config.storage.pragmas.apply(db.connection())?;
+    let jobs = db.active_jobs()?;
+    let db = Arc::new(Mutex::new(db));
+    let job_runner = service::Jobs::default();
+    for id in jobs {
+        job_runner.spawn(&db, id);
+    }
+
+    let body_limit = match config.service.body_limit {
+        Some(limit) => DefaultBodyLimit::max(limit),
+        None => DefaultBodyLimit::disable(),
+    };
+    let api_keys = Arc::new(service::ApiKeys {
+        api_key: config.service.api_key.clone(),
+        read_only_api_key: config.service.read_only_api_key.clone(),
+    });
+
+    let writer = service::Writer::spawn(&db)?;
+    let app = service::router(db.clone(), writer.clone(), job_runner.clone())
+        .layer(body_limit)
+        .layer(axum::middleware::from_fn_with_state(
+            api_keys,
+            service::auth,
+        ));
+
+    let listener = tokio::net::TcpListener::bind(&config.service.listen_addr).await?;
+    log::info!("Listening on: {}", config.service.listen_addr);
+
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    let server = axum::serve(listener, app).with_graceful_shutdown(async move {
+        shutdown_signal().await;
+        let _ = shutdown_tx.send(());
+    });
+    let mut server = std::pin::pin!(server.into_future());
+
+    tokio::select! {
+        r = &mut server => r?,
+        _ = shutdown_rx => {
+            let drain = Duration::from_secs(config.service.shutdown_timeout);
+            log::info!("Shutting down, draining in-flight requests for up to {:?}", drain);
+            match tokio::time::timeout(drain, server).await {
+                Ok(r) => r?,
+                Err(_) => log::warn!("Timed out draining in-flight requests"),
+            }
+        }
+    }
+
+    close(
+        db,
+        writer,
+        job_runner,
+        Duration::from_secs(config.service.shutdown_timeout),
+    )
+    .await
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::error!("Failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                log::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => log::info!("Received SIGINT"),
+        _ = terminate => log::info!("Received SIGTERM"),
+    }
+}
+
+async fn close(
+    db: Arc<Mutex<VectorStore>>,
+    writer: service::Writer,
+    jobs: service::Jobs,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    // Acknowledged `wait=false` writes are committed before closing, and the
+    // writer and job threads let go of the store. A task that outlived the
+    // drain can hold the connection indefinitely, so this gets its own
+    // timeout.
+    let flush = async {
+        writer.close().await;
+        tokio::task::spawn_blocking(move || jobs.stop()).await?;
+        anyhow::Ok(db.lock().await.checkpoint()?)
+    };
+    match tokio::time::timeout(timeout, flush).await {
+        Ok(r) => r?,
+        Err(_) => log::warn!(
+            "Timed out after {:?} flushing queued writes, skipping the WAL checkpoint",
+            timeout
+        ),
+    }
+    match Arc::try_unwrap(db) {
+        Ok(store) => store.into_inner().close()?,
+        // A request that outlived the drain timeout still holds the connection;
+        // it is closed when that task finishes or the process exits.
+        Err(_) => log::warn!("Database connection still in use, skipping close"),
+    }
+    log::info!("Shutdown complete");
     Ok(())
 }
@@ -1,16 +1,32 @@
-use std::sync::Arc;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, PoisonError, Weak,
+    },
+};
 
 use axum::{
-    extract::{Path, State},
-    response::IntoResponse,
-    Json,
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        Extension, FromRequest, FromRequestParts, Request, State,
+    },
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{delete, get, patch, post, put},
+    Json, Router,
 };
 
-use rusqlite::OptionalExtension;
+use rusqlite_vss::{
+    AliasDescription, AliasOperation, CollectionConfig, CollectionInfo, CollectionUpdate, Error,
+    EvaluateRequest, EvaluationReport, Filter, FusionRequest, GroupsResult, JobInfo, JobStatus,
+    MultiVectorParams, PayloadSchemaType, Point, PointOperation, QuantizationConfig, ScoredPoint,
+    ScrollRequest, ScrollResult, SearchGroupsRequest, SearchRequest, SparseSearchRequest,
+    SparseVectorParams, TrainRequest, VectorStore, VectorsConfig,
+};
 use tokio::sync::Mutex;
 
-use crate::store;
-
 #[derive(Debug, serde::Serialize)]
 pub struct APIResult<T> {
     pub result: T,
@@ -18,81 +34,741 @@ pub struct APIResult<T> {
     pub error: Option<String>,
 }
 
-#[derive(Debug, serde::Deserialize)]
-pub struct CreateConllections {
-    pub vectors: CreateConllectionsVectors,
+impl<T> APIResult<T> {
+    pub fn ok(result: T) -> Json<Self> {
+        Json(Self {
+            result,
+            status: Some("ok".to_string()),
+            error: None,
+        })
+    }
+}
+
+/// Every failed request is answered with the same `APIResult` body:
+/// `{"result": null, "status": "error", "error": "..."}`.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        let status = match &e {
+            Error::CollectionNotFound(_)
+            | Error::AliasNotFound(_)
+            | Error::JobNotFound(_)
+            | Error::PointNotFound(_) => StatusCode::NOT_FOUND,
+            Error::CollectionExists(_) | Error::IndexNotTrained(_) => StatusCode::CONFLICT,
+            Error::DimensionMismatch { .. }
+            | Error::InvalidFilter(_)
+            | Error::InvalidArgument(_) => StatusCode::BAD_REQUEST,
+            Error::InvalidVectorBlob { .. } | Error::Storage(_) | Error::Serialization(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        Self::new(status, e.to_string())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(e: JsonRejection) -> Self {
+        Self::new(
+            e.status(),
+            format!("Format error in JSON body: {}", e.body_text()),
+        )
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(e: PathRejection) -> Self {
+        Self::new(
+            e.status(),
+            format!("Format error in path: {}", e.body_text()),
+        )
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(e: QueryRejection) -> Self {
+        Self::new(
+            e.status(),
+            format!("Format error in query: {}", e.body_text()),
+        )
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if self.status.is_server_error() {
+            log::error!("{}", self.message);
+        } else {
+            log::warn!("{}", self.message);
+        }
+        (
+            self.status,
+            Json(APIResult::<Option<()>> {
+                result: None,
+                status: Some("error".to_string()),
+                error: Some(self.message),
+            }),
+        )
+            .into_response()
+    }
+}
+
+/// `Json` whose rejections are reported as [`ApiError`].
+#[derive(FromRequest)]
+#[from_request(via(Json), rejection(ApiError))]
+pub struct ApiJson<T>(pub T);
+
+/// `Path` whose rejections are reported as [`ApiError`].
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ApiError))]
+pub struct Path<T>(pub T);
+
+/// `Query` whose rejections are reported as [`ApiError`].
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub struct Query<T>(pub T);
+
+pub fn router(db: Arc<Mutex<VectorStore>>, writer: Writer, jobs: Jobs) -> Router {
+    Router::new()
+        .route("/", get(root))
+        .route("/healthz", get(healthz))
+        .route("/livez", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/collections/aliases", post(update_aliases))
+        .route("/aliases", get(list_aliases))
+        .route("/jobs", get(list_jobs))
+        .route("/jobs/:id", get(get_job))
+        .route("/jobs/:id", delete(cancel_job))
+        .route("/collections/:name", put(create_collections))
+        .route("/collections/:name", get(get_collections_info))
+        .route("/collections/:name", delete(delete_collection))
+        .route("/collections/:name", patch(update_collection))
+        .route("/collections/:name/rename", post(rename_collection))
+        .route("/collections/:name/clone", post(clone_collection))
+        .route("/collections/:name/index", put(create_field_index))
+        .route(
+            "/collections/:name/index/:field_name",
+            delete(delete_field_index),
+        )
+        .route("/collections/:name/train", post(train_collection))
+        .route("/collections/:name/evaluate", post(evaluate_collection))
+        .route("/collections/:name/points/:point_id", get(get_point))
+        .route("/collections/:name/points", put(add_points))
+        .route("/collections/:name/points/delete", post(delete_points))
+        .route("/collections/:name/points/batch", post(update_batch))
+        .route("/collections/:name/points/scroll", post(scroll_points))
+        .route("/collections/:name/points/search", post(search_points))
+        .route(
+            "/collections/:name/points/search/groups",
+            post(search_groups),
+        )
+        .route(
+            "/collections/:name/points/search/sparse",
+            post(search_sparse),
+        )
+        .route(
+            "/collections/:name/points/search/fusion",
+            post(search_fusion),
+        )
+        .route("/collections/:name/points", post(get_points))
+        .fallback(not_found)
+        .layer(Extension(writer))
+        .layer(Extension(jobs))
+        .with_state(db)
+}
+
+async fn not_found(request: Request) -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        format!("Not found: {} {}", request.method(), request.uri().path()),
+    )
+}
+
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    pub api_key: Option<String>,
+    pub read_only_api_key: Option<String>,
+}
+
+fn is_read_only_request(request: &Request) -> bool {
+    let path = request.uri().path().trim_end_matches('/');
+    match *request.method() {
+        axum::http::Method::GET | axum::http::Method::HEAD => true,
+        axum::http::Method::POST => {
+            path.ends_with("/points")
+                || path.ends_with("/points/scroll")
+                || path.contains("/points/search")
+                || path.ends_with("/evaluate")
+        }
+        _ => false,
+    }
+}
+
+/// Accepts the key in Qdrant's `api-key` header or as a bearer token.
+/// Health and version endpoints stay open for probes.
+pub async fn auth(State(keys): State<Arc<ApiKeys>>, request: Request, next: Next) -> Response {
+    if keys.api_key.is_none() && keys.read_only_api_key.is_none() {
+        return next.run(request).await;
+    }
+    if matches!(
+        request.uri().path(),
+        "/" | "/healthz" | "/livez" | "/readyz"
+    ) {
+        return next.run(request).await;
+    }
+
+    let headers = request.headers();
+    let provided = headers
+        .get("api-key")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| {
+            headers
+                .get(axum::http::header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+        });
+
+    let allowed = match provided {
+        Some(key) if keys.api_key.as_deref() == Some(key) => true,
+        Some(key) if keys.read_only_api_key.as_deref() == Some(key) => {
+            is_read_only_request(&request)
+        }
+        _ => false,
+    };
+
+    if allowed {
+        next.run(request).await
+    } else {
+        let status = if provided.is_some() {
+            StatusCode::FORBIDDEN
+        } else {
+            StatusCode::UNAUTHORIZED
+        };
+        ApiError::new(status, "Must provide a valid API key").into_response()
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct VersionInfo {
+    pub title: String,
+    pub version: String,
+}
+
+pub async fn root() -> impl IntoResponse {
+    Json(VersionInfo {
+        title: format!("{} - vector search engine", env!("CARGO_PKG_NAME")),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    })
+}
+
+pub async fn healthz() -> impl IntoResponse {
+    (StatusCode::OK, "healthz check passed")
+}
+
+pub async fn readyz(State(db): State<Arc<Mutex<VectorStore>>>) -> impl IntoResponse {
+    let store = db.lock().await;
+    match store.check_ready() {
+        Ok(_) => (StatusCode::OK, "all shards are ready".to_string()),
+        Err(e) => {
+            log::error!("Readiness check failed: {}", e);
+            (StatusCode::SERVICE_UNAVAILABLE, format!("not ready: {}", e))
+        }
+    }
 }
 
 #[derive(Debug, serde::Deserialize)]
-pub struct CreateConllectionsVectors {
-    pub size: usize,
+pub struct CreateConllections {
+    pub vectors: VectorsConfig,
+    #[serde(default)]
+    pub sparse_vectors: BTreeMap<String, SparseVectorParams>,
+    #[serde(default)]
+    pub multivectors: BTreeMap<String, MultiVectorParams>,
+    pub index_factory: Option<String>,
+    pub quantization_config: Option<QuantizationConfig>,
 }
 
 pub type CreateConllectionsResult = APIResult<bool>;
 
 pub async fn create_collections(
     Path(name): Path<String>,
-    State(db): State<Arc<Mutex<rusqlite::Connection>>>,
-    Json(create_conllections): Json<CreateConllections>,
-) -> impl IntoResponse {
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    ApiJson(create_conllections): ApiJson<CreateConllections>,
+) -> Result<Json<CreateConllectionsResult>, ApiError> {
     log::info!("Create collection: {}", name);
-    let conn = db.lock().await;
-    if let Err(e) = store::create_collections(&conn, &name, create_conllections.vectors.size) {
-        log::error!("Failed to create collection: {}", e);
-        return (
-            axum::http::StatusCode::CONFLICT,
-            Json(CreateConllectionsResult {
-                result: false,
-                status: None,
-                error: Some(e.to_string()),
-            }),
-        );
-    } else {
-        return (
-            axum::http::StatusCode::OK,
-            Json(CreateConllectionsResult {
-                result: true,
-                status: Some("ok".to_string()),
-                error: None,
-            }),
-        );
+    let mut config = CollectionConfig::with_vectors(create_conllections.vectors);
+    config.sparse_vectors = create_conllections.sparse_vectors;
+    config.multivectors = create_conllections.multivectors;
+    config.index_factory = create_conllections.index_factory;
+    config.quantization_config = create_conllections.quantization_config;
+    let store = db.lock().await;
+    store.create_collection_with_config(&name, &config)?;
+    Ok(APIResult::ok(true))
+}
+
+pub type UpdateCollectionResult = APIResult<JobInfo>;
+
+pub async fn update_collection(
+    Path(name): Path<String>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    Extension(jobs): Extension<Jobs>,
+    ApiJson(update): ApiJson<CollectionUpdate>,
+) -> Result<Json<UpdateCollectionResult>, ApiError> {
+    log::info!("Update collection: {}", name);
+    let store = db.lock().await;
+    let job = store.collection(&name)?.update(&update)?;
+    jobs.spawn(&db, job.id);
+    Ok(APIResult::ok(job))
+}
+
+/// Points a job processes per lock of the store.
+const JOB_BATCH_SIZE: usize = 1_000;
+
+/// The threads running jobs, which [`Jobs::stop`] stops on shutdown.
+#[derive(Clone, Default)]
+pub struct Jobs {
+    stopping: Arc<AtomicBool>,
+    threads: Arc<std::sync::Mutex<Vec<std::thread::JoinHandle<()>>>>,
+}
+
+impl Jobs {
+    /// Runs job `id` on a dedicated thread. The thread locks the store for
+    /// one batch at a time, so requests are served between batches, and
+    /// stops once the job is done or the jobs are stopped.
+    pub fn spawn(&self, db: &Arc<Mutex<VectorStore>>, id: u64) {
+        let mut threads = self.threads.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.stopping.load(Ordering::SeqCst) {
+            log::info!("Shutting down, job {} starts on restart", id);
+            return;
+        }
+        threads.retain(|thread| !thread.is_finished());
+        let db = Arc::downgrade(db);
+        let stopping = self.stopping.clone();
+        let spawned = std::thread::Builder::new()
+            .name(format!("job-{}", id))
+            .spawn(move || run_job(db, stopping, id));
+        match spawned {
+            Ok(thread) => threads.push(thread),
+            Err(e) => log::error!("Failed to start job {}: {}", id, e),
+        }
+    }
+
+    /// Stops every job after its current batch and waits for the threads
+    /// to exit, so none of them holds the store any more. The jobs resume
+    /// on restart. Blocks.
+    pub fn stop(&self) {
+        let threads = {
+            let mut threads = self.threads.lock().unwrap_or_else(PoisonError::into_inner);
+            self.stopping.store(true, Ordering::SeqCst);
+            std::mem::take(&mut *threads)
+        };
+        for thread in threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run_job(db: Weak<Mutex<VectorStore>>, stopping: Arc<AtomicBool>, id: u64) {
+    log::info!("Running job {}", id);
+    loop {
+        if stopping.load(Ordering::SeqCst) {
+            log::info!("Shutting down, job {} resumes on restart", id);
+            break;
+        }
+        let Some(db) = db.upgrade() else {
+            log::info!("Store closed, job {} resumes on restart", id);
+            break;
+        };
+        let step = db.blocking_lock().run_job_step(id, JOB_BATCH_SIZE);
+        match step {
+            Ok(job) if job.status.is_active() => {}
+            Ok(job) if job.status == JobStatus::Failed => {
+                log::error!(
+                    "Job {} failed: {}",
+                    id,
+                    job.error.as_deref().unwrap_or_default()
+                );
+                break;
+            }
+            Ok(job) => {
+                log::info!("Job {} {:?}", id, job.status);
+                break;
+            }
+            Err(e) => {
+                log::error!("Job {} stopped: {}", id, e);
+                break;
+            }
+        }
     }
 }
 
+pub type JobsResult = APIResult<Vec<JobInfo>>;
+
+pub async fn list_jobs(
+    State(db): State<Arc<Mutex<VectorStore>>>,
+) -> Result<Json<JobsResult>, ApiError> {
+    let store = db.lock().await;
+    Ok(APIResult::ok(store.jobs()?))
+}
+
+pub type JobResult = APIResult<JobInfo>;
+
+pub async fn get_job(
+    Path(id): Path<u64>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+) -> Result<Json<JobResult>, ApiError> {
+    let store = db.lock().await;
+    Ok(APIResult::ok(store.job(id)?))
+}
+
+pub async fn cancel_job(
+    Path(id): Path<u64>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+) -> Result<Json<JobResult>, ApiError> {
+    log::info!("Cancel job: {}", id);
+    let store = db.lock().await;
+    Ok(APIResult::ok(store.cancel_job(id)?))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct RenameCollection {
+    pub new_name: String,
+}
+
+pub type RenameCollectionResult = APIResult<bool>;
+
+pub async fn rename_collection(
+    Path(name): Path<String>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    ApiJson(rename): ApiJson<RenameCollection>,
+) -> Result<Json<RenameCollectionResult>, ApiError> {
+    log::info!("Rename collection: {} to {}", name, rename.new_name);
+    let store = db.lock().await;
+    store.rename_collection(&name, &rename.new_name)?;
+    Ok(APIResult::ok(true))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CloneCollection {
+    pub new_name: String,
+    pub filter: Option<Filter>,
+}
+
+pub type CloneCollectionResult = APIResult<bool>;
+
+pub async fn clone_collection(
+    Path(name): Path<String>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    ApiJson(clone): ApiJson<CloneCollection>,
+) -> Result<Json<CloneCollectionResult>, ApiError> {
+    log::info!("Clone collection: {} to {}", name, clone.new_name);
+    let store = db.lock().await;
+    store.clone_collection(&name, &clone.new_name, clone.filter.as_ref())?;
+    Ok(APIResult::ok(true))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CreateFieldIndex {
+    pub field_name: String,
+    pub field_schema: PayloadSchemaType,
+}
+
+pub type FieldIndexResult = APIResult<bool>;
+
+pub async fn create_field_index(
+    Path(name): Path<String>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    ApiJson(index): ApiJson<CreateFieldIndex>,
+) -> Result<Json<FieldIndexResult>, ApiError> {
+    log::info!("Create field index: {} {}", name, index.field_name);
+    let store = db.lock().await;
+    store
+        .collection(&name)?
+        .create_field_index(&index.field_name, index.field_schema)?;
+    Ok(APIResult::ok(true))
+}
+
+pub async fn delete_field_index(
+    Path((name, field_name)): Path<(String, String)>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+) -> Result<Json<FieldIndexResult>, ApiError> {
+    log::info!("Delete field index: {} {}", name, field_name);
+    let store = db.lock().await;
+    store.collection(&name)?.delete_field_index(&field_name)?;
+    Ok(APIResult::ok(true))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ChangeAliases {
+    pub actions: Vec<AliasOperation>,
+}
+
+pub type ChangeAliasesResult = APIResult<bool>;
+
+pub async fn update_aliases(
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    ApiJson(change): ApiJson<ChangeAliases>,
+) -> Result<Json<ChangeAliasesResult>, ApiError> {
+    log::info!("Update aliases: {} actions", change.actions.len());
+    let store = db.lock().await;
+    store.update_aliases(&change.actions)?;
+    Ok(APIResult::ok(true))
+}
+
 #[derive(Debug, serde::Serialize)]
-pub struct CollectionsInfo {
-    pub points_count: u64,
+pub struct Aliases {
+    pub aliases: Vec<AliasDescription>,
+}
+
+pub type ListAliasesResult = APIResult<Aliases>;
+
+pub async fn list_aliases(
+    State(db): State<Arc<Mutex<VectorStore>>>,
+) -> Result<Json<ListAliasesResult>, ApiError> {
+    let store = db.lock().await;
+    let aliases = store.aliases()?;
+    Ok(APIResult::ok(Aliases { aliases }))
 }
 
-pub type GetCollectionsResult = APIResult<CollectionsInfo>;
+pub type GetCollectionsResult = APIResult<CollectionInfo>;
 
 pub async fn get_collections_info(
     Path(name): Path<String>,
-    State(db): State<Arc<Mutex<rusqlite::Connection>>>,
-) -> impl IntoResponse {
+    State(db): State<Arc<Mutex<VectorStore>>>,
+) -> Result<Json<GetCollectionsResult>, ApiError> {
     log::info!("Get collection info: {}", name);
-    let conn = db.lock().await;
-    match store::get_collections_info(&conn, &name) {
-        Ok(info) => (
-            axum::http::StatusCode::OK,
-            Json(GetCollectionsResult {
-                result: CollectionsInfo {
-                    points_count: info.points_count,
-                },
-                status: Some("ok".to_string()),
-                error: None,
-            }),
-        ),
-        Err(e) => {
-            log::error!("Failed to get collection info: {}", e);
-            (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                Json(GetCollectionsResult {
-                    result: CollectionsInfo { points_count: 0 },
-                    status: None,
-                    error: Some(e.to_string()),
-                }),
-            )
+    let store = db.lock().await;
+    let info = store.collection(&name)?.info()?;
+    Ok(APIResult::ok(info))
+}
+
+pub type TrainResult = APIResult<JobInfo>;
+
+pub async fn train_collection(
+    Path(name): Path<String>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    Extension(jobs): Extension<Jobs>,
+    ApiJson(train): ApiJson<TrainRequest>,
+) -> Result<Json<TrainResult>, ApiError> {
+    log::info!("Train collection: {}", name);
+    let store = db.lock().await;
+    let job = store.collection(&name)?.start_training(train.sample_size)?;
+    jobs.spawn(&db, job.id);
+    Ok(APIResult::ok(job))
+}
+
+pub type EvaluateResult = APIResult<EvaluationReport>;
+
+pub async fn evaluate_collection(
+    Path(name): Path<String>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    ApiJson(evaluate): ApiJson<EvaluateRequest>,
+) -> Result<Json<EvaluateResult>, ApiError> {
+    log::info!("Evaluate collection: {}", name);
+    let store = db.lock().await;
+    let report = store.collection(&name)?.evaluate(&evaluate)?;
+    Ok(APIResult::ok(report))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct WriteParams {
+    /// With `false` the write is only queued, and answered with an
+    /// [`UpdateResult`] before it is applied.
+    #[serde(default = "WriteParams::default_wait")]
+    pub wait: bool,
+}
+
+impl WriteParams {
+    fn default_wait() -> bool {
+        true
+    }
+}
+
+/// The answer to a `wait=false` write.
+#[derive(Debug, serde::Serialize)]
+pub struct UpdateResult {
+    pub operation_id: u64,
+    pub status: &'static str,
+}
+
+impl UpdateResult {
+    fn acknowledged(operation_id: u64) -> Self {
+        Self {
+            operation_id,
+            status: "acknowledged",
+        }
+    }
+}
+
+/// Writes that can be queued before `write` waits for room.
+const WRITE_QUEUE_SIZE: usize = 1_024;
+
+/// Most writes committed in one transaction.
+const MAX_WRITE_GROUP: usize = 256;
+
+enum QueuedWrite {
+    Write {
+        operation_id: u64,
+        collection: String,
+        operations: Vec<PointOperation>,
+        /// Set for `wait=true`, which is answered once the write is
+        /// committed.
+        done: Option<tokio::sync::oneshot::Sender<Result<(), Error>>>,
+    },
+    /// Stops the writer once the writes queued before are applied.
+    Close(tokio::sync::oneshot::Sender<()>),
+}
+
+/// Point writes, applied in the order they were queued by a dedicated
+/// thread that commits the writes queued meanwhile together.
+#[derive(Clone)]
+pub struct Writer {
+    queue: tokio::sync::mpsc::Sender<QueuedWrite>,
+    operation_id: Arc<AtomicU64>,
+}
+
+impl Writer {
+    /// Starts the writer thread. It stops once every `Writer` is dropped.
+    pub fn spawn(db: &Arc<Mutex<VectorStore>>) -> std::io::Result<Self> {
+        let (queue, receiver) = tokio::sync::mpsc::channel(WRITE_QUEUE_SIZE);
+        let db = Arc::downgrade(db);
+        std::thread::Builder::new()
+            .name("writer".to_string())
+            .spawn(move || run_writer(db, receiver))?;
+        // Ids count up from the current time in microseconds, so a restart
+        // doesn't hand out the ids of the previous run again.
+        let first_id = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_micros() as u64);
+        Ok(Self {
+            queue,
+            operation_id: Arc::new(AtomicU64::new(first_id)),
+        })
+    }
+
+    /// Queues `operations` on `collection`, to be applied together or not
+    /// at all. With `wait` returns once they are committed, with the error
+    /// if they failed. Otherwise checks them against the collection first,
+    /// then returns the operation id right away; a failure after that is
+    /// only logged.
+    pub async fn write(
+        &self,
+        db: &Mutex<VectorStore>,
+        collection: String,
+        operations: Vec<PointOperation>,
+        wait: bool,
+    ) -> Result<Option<u64>, ApiError> {
+        if !wait {
+            db.lock()
+                .await
+                .collection(&collection)?
+                .check(&operations)?;
+        }
+        let operation_id = self.operation_id.fetch_add(1, Ordering::Relaxed);
+        let (done, result) = match wait {
+            true => {
+                let (done, result) = tokio::sync::oneshot::channel();
+                (Some(done), Some(result))
+            }
+            false => (None, None),
+        };
+        let write = QueuedWrite::Write {
+            operation_id,
+            collection,
+            operations,
+            done,
+        };
+        self.queue.send(write).await.map_err(|_| writer_stopped())?;
+        match result {
+            Some(result) => {
+                result.await.map_err(|_| writer_stopped())??;
+                Ok(None)
+            }
+            None => Ok(Some(operation_id)),
+        }
+    }
+
+    /// Applies every write queued before and stops the writer thread, so
+    /// it no longer holds the store. Later writes fail.
+    pub async fn close(&self) {
+        let (done, closed) = tokio::sync::oneshot::channel();
+        if self.queue.send(QueuedWrite::Close(done)).await.is_ok() {
+            let _ = closed.await;
+        }
+    }
+}
+
+fn writer_stopped() -> ApiError {
+    ApiError::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Service internal error: the writer has stopped",
+    )
+}
+
+fn run_writer(db: Weak<Mutex<VectorStore>>, mut queue: tokio::sync::mpsc::Receiver<QueuedWrite>) {
+    while let Some(first) = queue.blocking_recv() {
+        let mut group = vec![];
+        let mut closed = None;
+        let mut next = Some(first);
+        while let Some(queued) = next.take() {
+            match queued {
+                QueuedWrite::Write {
+                    operation_id,
+                    collection,
+                    operations,
+                    done,
+                } => group.push((operation_id, collection, operations, done)),
+                QueuedWrite::Close(done) => {
+                    closed = Some(done);
+                    break;
+                }
+            }
+            if group.len() < MAX_WRITE_GROUP {
+                next = queue.try_recv().ok();
+            }
+        }
+
+        if !group.is_empty() {
+            let Some(db) = db.upgrade() else {
+                log::error!("Store closed, dropping {} queued writes", group.len());
+                return;
+            };
+            let writes = group
+                .iter()
+                .map(|(_, collection, operations, _)| (collection.as_str(), operations.as_slice()))
+                .collect::<Vec<_>>();
+            let results = db.blocking_lock().apply_writes(&writes);
+            for ((operation_id, collection, _, done), result) in group.into_iter().zip(results) {
+                match (done, result) {
+                    (Some(done), result) => {
+                        let _ = done.send(result);
+                    }
+                    (None, Err(e)) => log::error!(
+                        "Operation {} on collection {} failed: {}",
+                        operation_id,
+                        collection,
+                        e
+                    ),
+                    (None, Ok(())) => {}
+                }
+            }
+        }
+        if let Some(done) = closed {
+            log::info!("Writer stopped");
+            let _ = done.send(());
+            return;
         }
     }
 }
@@ -102,43 +778,30 @@ pub struct AddPoints {
     pub points: Vec<Point>,
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
-pub struct Point {
-    pub id: u64,
-    pub vector: Vec<f32>,
-    pub payload: Option<serde_json::Map<String, serde_json::Value>>,
-}
-
 pub type AddPointsResult = APIResult<Option<Vec<u64>>>;
 
 pub async fn add_points(
     Path(name): Path<String>,
-    State(db): State<Arc<Mutex<rusqlite::Connection>>>,
-    Json(points): Json<AddPoints>,
-) -> impl IntoResponse {
+    Query(params): Query<WriteParams>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    Extension(writer): Extension<Writer>,
+    ApiJson(points): ApiJson<AddPoints>,
+) -> Result<Response, ApiError> {
     log::info!("Add points: {}", name);
+    let ids: Vec<u64> = points.points.iter().map(|p| p.id).collect();
+    let operation = PointOperation::Upsert {
+        points: points.points,
+    };
+    match writer
+        .write(&db, name, vec![operation], params.wait)
+        .await?
     {
-        let conn = db.lock().await;
-        match store::add_point(&conn, &name, &points.points) {
-            Ok(success_id) => (
-                axum::http::StatusCode::OK,
-                Json(AddPointsResult {
-                    result: Some(success_id),
-                    status: Some("ok".to_string()),
-                    error: None,
-                }),
-            ),
-            Err(e) => {
-                log::error!("Failed to add points: {}", e);
-                (
-                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(AddPointsResult {
-                        result: None,
-                        status: None,
-                        error: Some(e.to_string()),
-                    }),
-                )
-            }
+        Some(operation_id) => {
+            Ok(APIResult::ok(UpdateResult::acknowledged(operation_id)).into_response())
+        }
+        None => {
+            let result: Json<AddPointsResult> = APIResult::ok(Some(ids));
+            Ok(result.into_response())
         }
     }
 }
@@ -152,133 +815,90 @@ pub type GetPointsResult = APIResult<Option<Vec<Point>>>;
 
 pub async fn get_points(
     Path(name): Path<String>,
-    State(db): State<Arc<Mutex<rusqlite::Connection>>>,
-    Json(ids): Json<GetPoints>,
-) -> impl IntoResponse {
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    ApiJson(ids): ApiJson<GetPoints>,
+) -> Result<Json<GetPointsResult>, ApiError> {
     log::info!("Get points: {}", name);
-    let r = {
-        let conn = db.lock().await;
-        store::get_points(&conn, &name, ids.ids).optional()
-    };
-
-    match r {
-        Ok(Some(points)) => (
-            axum::http::StatusCode::OK,
-            Json(GetPointsResult {
-                result: Some(points),
-                status: Some("ok".to_string()),
-                error: None,
-            }),
-        ),
-        Ok(None) => (
-            axum::http::StatusCode::OK,
-            Json(GetPointsResult {
-                result: Some(Vec::new()),
-                status: Some("ok".to_string()),
-                error: None,
-            }),
-        ),
-        Err(e) => (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Json(GetPointsResult {
-                result: None,
-                status: None,
-                error: Some(e.to_string()),
-            }),
-        ),
-    }
+    let store = db.lock().await;
+    let points = store.collection(&name)?.get_many(ids.ids)?;
+    Ok(APIResult::ok(Some(points)))
 }
 
 pub type GetPointResult = APIResult<Option<Point>>;
 
 pub async fn get_point(
     Path((name, point_id)): Path<(String, u64)>,
-    State(db): State<Arc<Mutex<rusqlite::Connection>>>,
-) -> impl IntoResponse {
+    State(db): State<Arc<Mutex<VectorStore>>>,
+) -> Result<Json<GetPointResult>, ApiError> {
     log::info!("Get point: {} {}", name, point_id);
-    let conn: tokio::sync::MutexGuard<rusqlite::Connection> = db.lock().await;
-    let r = store::get_point(&conn, &name, point_id).optional();
-    match r {
-        Ok(Some(point)) => (
-            axum::http::StatusCode::OK,
-            Json(GetPointResult {
-                result: Some(point),
-                status: Some("ok".to_string()),
-                error: None,
-            }),
-        ),
-        Ok(None) => (
-            axum::http::StatusCode::NOT_FOUND,
-            Json(GetPointResult {
-                result: None,
-                status: None,
-                error: Some(format!(
-                    "Not found: Point with id {} does not exists",
-                    point_id
-                )),
-            }),
-        ),
-        Err(e) => (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Json(GetPointResult {
-                result: None,
-                status: None,
-                error: Some(e.to_string()),
-            }),
-        ),
+    let store = db.lock().await;
+    match store.collection(&name)?.get(point_id)? {
+        Some(point) => Ok(APIResult::ok(Some(point))),
+        None => Err(Error::PointNotFound(point_id).into()),
     }
 }
 
-#[derive(Debug, serde::Deserialize)]
-pub struct Search {
-    pub vector: Vec<f32>,
-    pub limit: usize,
-}
+pub type ScrollPointsResult = APIResult<ScrollResult>;
 
-#[derive(Debug, serde::Serialize)]
-pub struct ScoredPoint {
-    pub id: u64,
-    pub vector: Vec<f32>,
-    pub payload: Option<serde_json::Map<String, serde_json::Value>>,
-    pub score: f32,
+pub async fn scroll_points(
+    Path(name): Path<String>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    ApiJson(scroll): ApiJson<ScrollRequest>,
+) -> Result<Json<ScrollPointsResult>, ApiError> {
+    log::info!("Scroll points: {}", name);
+    let store = db.lock().await;
+    let result = store.collection(&name)?.scroll(&scroll)?;
+    Ok(APIResult::ok(result))
 }
 
+pub type Search = SearchRequest;
+
 pub type SearchResult = APIResult<Option<Vec<ScoredPoint>>>;
 
 pub async fn search_points(
     Path(name): Path<String>,
-    State(db): State<Arc<Mutex<rusqlite::Connection>>>,
-    Json(search): Json<Search>,
-) -> impl IntoResponse {
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    ApiJson(search): ApiJson<Search>,
+) -> Result<Json<SearchResult>, ApiError> {
     log::info!("Search points: {}", name);
-    let conn = db.lock().await;
-    let r = store::search_points(&conn, &name, search.vector.as_slice(), search.limit).optional();
-    match r {
-        Ok(Some(points)) => (
-            axum::http::StatusCode::OK,
-            Json(SearchResult {
-                result: Some(points),
-                status: Some("ok".to_string()),
-                error: None,
-            }),
-        ),
-        Ok(None) => (
-            axum::http::StatusCode::OK,
-            Json(SearchResult {
-                result: Some(Vec::new()),
-                status: Some("ok".to_string()),
-                error: None,
-            }),
-        ),
-        Err(e) => (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Json(SearchResult {
-                result: None,
-                status: None,
-                error: Some(e.to_string()),
-            }),
-        ),
-    }
+    let store = db.lock().await;
+    let points = store.collection(&name)?.search_with(&search)?;
+    Ok(APIResult::ok(Some(points)))
+}
+
+pub type SearchGroupsResult = APIResult<GroupsResult>;
+
+pub async fn search_groups(
+    Path(name): Path<String>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    ApiJson(search): ApiJson<SearchGroupsRequest>,
+) -> Result<Json<SearchGroupsResult>, ApiError> {
+    log::info!("Search point groups: {}", name);
+    let store = db.lock().await;
+    let groups = store.collection(&name)?.search_groups(&search)?;
+    Ok(APIResult::ok(GroupsResult { groups }))
+}
+
+pub async fn search_sparse(
+    Path(name): Path<String>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    ApiJson(search): ApiJson<SparseSearchRequest>,
+) -> Result<Json<SearchResult>, ApiError> {
+    log::info!("Search sparse points: {}", name);
+    let store = db.lock().await;
+    let points = store.collection(&name)?.search_sparse(&search)?;
+    Ok(APIResult::ok(Some(points)))
+}
+
+pub async fn search_fusion(
+    Path(name): Path<String>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    ApiJson(search): ApiJson<FusionRequest>,
+) -> Result<Json<SearchResult>, ApiError> {
+    log::info!("Search fusion points: {}", name);
+    let store = db.lock().await;
+    let points = store.collection(&name)?.search_fusion(&search)?;
+    Ok(APIResult::ok(Some(points)))
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -290,53 +910,133 @@ pub type DeletePointsResult = APIResult<bool>;
 
 pub async fn delete_points(
     Path(name): Path<String>,
-    State(db): State<Arc<Mutex<rusqlite::Connection>>>,
-    Json(points): Json<DeletePoints>,
-) -> impl IntoResponse {
+    Query(params): Query<WriteParams>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    Extension(writer): Extension<Writer>,
+    ApiJson(points): ApiJson<DeletePoints>,
+) -> Result<Response, ApiError> {
     log::info!("Delete points: {}", name);
-    let conn = db.lock().await;
-    match store::delete_points(&conn, &name, points.points) {
-        Ok(_) => (
-            axum::http::StatusCode::OK,
-            Json(DeletePointsResult {
-                result: true,
-                status: Some("ok".to_string()),
-                error: None,
-            }),
-        ),
-        Err(e) => (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Json(DeletePointsResult {
-                result: false,
-                status: None,
-                error: Some(e.to_string()),
-            }),
-        ),
+    let operation = PointOperation::Delete {
+        points: points.points,
+    };
+    match writer
+        .write(&db, name, vec![operation], params.wait)
+        .await?
+    {
+        Some(operation_id) => {
+            Ok(APIResult::ok(UpdateResult::acknowledged(operation_id)).into_response())
+        }
+        None => {
+            let result: Json<DeletePointsResult> = APIResult::ok(true);
+            Ok(result.into_response())
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct UpdateBatch {
+    pub operations: Vec<PointOperation>,
+}
+
+pub type UpdateBatchResult = APIResult<bool>;
+
+pub async fn update_batch(
+    Path(name): Path<String>,
+    Query(params): Query<WriteParams>,
+    State(db): State<Arc<Mutex<VectorStore>>>,
+    Extension(writer): Extension<Writer>,
+    ApiJson(batch): ApiJson<UpdateBatch>,
+) -> Result<Response, ApiError> {
+    log::info!(
+        "Update points batch: {} {} operations",
+        name,
+        batch.operations.len()
+    );
+    match writer
+        .write(&db, name, batch.operations, params.wait)
+        .await?
+    {
+        Some(operation_id) => {
+            Ok(APIResult::ok(UpdateResult::acknowledged(operation_id)).into_response())
+        }
+        None => {
+            let result: Json<UpdateBatchResult> = APIResult::ok(true);
+            Ok(result.into_response())
+        }
     }
 }
 
 pub async fn delete_collection(
     Path(name): Path<String>,
-    State(db): State<Arc<Mutex<rusqlite::Connection>>>,
-) -> impl IntoResponse {
+    State(db): State<Arc<Mutex<VectorStore>>>,
+) -> Result<Json<DeletePointsResult>, ApiError> {
     log::info!("Delete collection: {}", name);
-    let conn = db.lock().await;
-    match store::delete_collection(&conn, &name) {
-        Ok(_) => (
-            axum::http::StatusCode::OK,
-            Json(DeletePointsResult {
-                result: true,
-                status: Some("ok".to_string()),
-                error: None,
-            }),
-        ),
-        Err(e) => (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Json(DeletePointsResult {
-                result: false,
-                status: None,
-                error: Some(e.to_string()),
-            }),
-        ),
+    let store = db.lock().await;
+    store.delete_collection(&name)?;
+    Ok(APIResult::ok(true))
+}
+
+#[cfg(test)]
+fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-' | b'.' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #![proptest_config(proptest::prelude::ProptestConfig::with_cases(64))]
+
+    /// Random names, ids and vectors must come back as an `APIResult` body,
+    /// never as a panic in a handler.
+    #[test]
+    fn test_http_no_panics(
+        name in "[a-zA-Z0-9_\\-.;'% ]{0,12}|test_vss",
+        size in 0usize..6,
+        id in proptest::prelude::any::<u64>(),
+        raw_id in "[0-9a-z-]{0,24}",
+        vector in proptest::collection::vec(-1e6f32..1e6f32, 0..6),
+        limit in proptest::prelude::any::<usize>(),
+    ) {
+        use tower::ServiceExt;
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let db = Arc::new(Mutex::new(VectorStore::open_in_memory().unwrap()));
+        let app = router(db.clone(), Writer::spawn(&db).unwrap(), Jobs::default());
+        let name = encode_path_segment(&name);
+        let raw_id = encode_path_segment(&raw_id);
+        let requests = [
+            ("PUT", format!("/collections/{}", name), serde_json::json!({"vectors": {"size": size}})),
+            ("PUT", format!("/collections/{}/points", name), serde_json::json!({"points": [{"id": id, "vector": vector, "payload": {"id": raw_id}}]})),
+            ("PUT", format!("/collections/{}/points?wait=false", name), serde_json::json!({"points": [{"id": id, "vector": vector}]})),
+            ("POST", format!("/collections/{}/points/search", name), serde_json::json!({"vector": vector, "limit": limit})),
+            ("POST", format!("/collections/{}/points", name), serde_json::json!({"ids": [id]})),
+            ("GET", format!("/collections/{}/points/{}", name, id), serde_json::Value::Null),
+            ("GET", format!("/collections/{}/points/{}", name, raw_id), serde_json::Value::Null),
+            ("GET", format!("/collections/{}", name), serde_json::Value::Null),
+            ("POST", format!("/collections/{}/points/batch", name), serde_json::json!({"operations": [{"set_payload": {"payload": {"id": raw_id}, "points": [id]}}, {"update_vectors": {"points": [{"id": id, "vector": vector}]}}]})),
+            ("POST", format!("/collections/{}/points/delete", name), serde_json::json!({"points": [id]})),
+            ("DELETE", format!("/collections/{}", name), serde_json::Value::Null),
+        ];
+
+        for (method, uri, body) in requests {
+            let request = Request::builder()
+                .method(method)
+                .uri(&uri)
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(body.to_string()))
+                .unwrap();
+            let response = runtime.block_on(app.clone().oneshot(request)).unwrap();
+            let bytes = runtime
+                .block_on(axum::body::to_bytes(response.into_body(), usize::MAX))
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes)
+                .unwrap_or_else(|e| panic!("{} {}: non-JSON body {:?}: {}", method, uri, bytes, e));
+            proptest::prop_assert!(body.get("status").is_some(), "{} {}: {}", method, uri, body);
+        }
     }
 }
@@ -1,9 +1,27 @@
-use std::{collections::HashMap, mem::size_of};
+use std::{
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet},
+    time::Instant,
+};
 
-use rusqlite::{ffi::sqlite3_auto_extension, params, Connection};
+use rusqlite::{
+    ffi::sqlite3_auto_extension, params, params_from_iter, types::Value, Connection,
+    OptionalExtension,
+};
 use sqlite_vss::{sqlite3_vector_init, sqlite3_vss_init};
 
-use crate::service::{CollectionsInfo, Point, ScoredPoint};
+use crate::{
+    filter::{self, SqlFilter},
+    geo, jobs,
+    types::{
+        AliasDescription, AliasOperation, CollectionConfig, CollectionUpdate, Direction,
+        EvaluateRequest, EvaluationReport, Fusion, FusionRequest, IndexRebuild, NamedMultiVector,
+        NamedVector, NamedVectorStruct, PayloadIndexInfo, PayloadSchemaType, PointGroup,
+        QuantizationConfig, QuantizationInfo, RebuildInfo, ScrollRequest, ScrollResult,
+        SearchGroupsRequest, SparseSearchRequest, SparseVector, TrainReport, Vector, VectorStruct,
+    },
+    CollectionInfo, Error, Filter, Payload, Point, PointOperation, Result, ScoredPoint,
+    SearchRequest,
+};
 
 pub fn init() {
     unsafe {
@@ -12,462 +30,4606 @@ pub fn init() {
     }
 }
 
-pub fn open(path: &str) -> rusqlite::Result<Connection> {
-    rusqlite::Connection::open(path)
-}
+/// Registers the geo SQL functions on `conn`, creates the `collections`
+/// metadata table and registers collections that were created before it
+/// existed, reading their dimension back from the `vss0(point(N))`
+/// declaration. Then sets up the `jobs` table, see [`jobs::init_schema`].
+pub fn init_schema(conn: &Connection) -> Result<()> {
+    geo::register_functions(conn)?;
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS collections (name TEXT PRIMARY KEY, config TEXT NOT NULL);
+        CREATE TABLE IF NOT EXISTS aliases (name TEXT PRIMARY KEY, collection TEXT NOT NULL);
+        "#,
+    )?;
 
-pub fn create_collections(conn: &Connection, name: &str, size: usize) -> rusqlite::Result<()> {
-    let sql = format!(
+    let mut stmt = conn.prepare(
         r#"
-        BEGIN;
-        CREATE VIRTUAL TABLE IF NOT EXISTS vss_{} USING vss0(point({}));
-        CREATE TABLE IF NOT EXISTS vss_{}_payload (rowid INTEGER PRIMARY KEY, payload TEXT);
-        COMMIT;
+        SELECT name, sql FROM sqlite_master
+        WHERE type = 'table' AND name LIKE 'vss\_%' ESCAPE '\' AND sql LIKE '%USING vss0(%'
+          AND substr(name, 5) NOT IN (SELECT name FROM collections);
         "#,
-        name, size, name
+    )?;
+    let legacy = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let collections = collection_names(conn)?;
+    for (table, sql) in legacy {
+        let name = &table["vss_".len()..];
+        // A rebuilt index lives in `vss_{name}_g{N}`, which is vss0 too.
+        if collections.iter().any(|c| is_internal_name(name, c)) {
+            continue;
+        }
+        let size = sql
+            .split_once("point(")
+            .and_then(|(_, rest)| rest.split_once(')'))
+            .and_then(|(size, _)| size.trim().parse::<usize>().ok());
+        if let Some(size) = size {
+            log::info!("Registering existing collection {} (size {})", name, size);
+            save_collection_config(conn, name, &CollectionConfig::new(size))?;
+        }
+    }
+    jobs::init_schema(conn)
+}
+
+fn save_collection_config(conn: &Connection, name: &str, config: &CollectionConfig) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO collections (name, config) VALUES (?1, ?2)",
+        params![name, serde_json::to_string(config)?],
+    )?;
+    Ok(())
+}
+
+/// Collection names are interpolated into table names, so they are limited
+/// to characters that are valid in an unquoted SQLite identifier.
+fn check_name(name: &str) -> Result<()> {
+    check_identifier("collection name", name)
+}
+
+fn collection_names(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM collections")?;
+    let names = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(names)
+}
+
+/// Whether the tables of collection `name` would share names with the
+/// internal tables of `owner`, e.g. `docs_payload` or `docs_g2` for `docs`.
+/// The suffixes cover the tables created here and the `_index` / `_data`
+/// shadow tables of vss0.
+fn is_internal_name(name: &str, owner: &str) -> bool {
+    let Some(rest) = name
+        .strip_prefix(owner)
+        .and_then(|rest| rest.strip_prefix('_'))
+    else {
+        return false;
+    };
+    let word = rest.split('_').next().unwrap_or_default();
+    let generation = word
+        .strip_prefix('g')
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+    generation
+        || matches!(
+            word,
+            "payload" | "geo" | "vectors" | "sparse" | "mv" | "index" | "data"
+        )
+}
+
+fn check_identifier(kind: &str, name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(Error::InvalidArgument(format!(
+            "{} `{}` must be 1-64 characters of [A-Za-z0-9_]",
+            kind, name
+        )));
+    }
+    Ok(())
+}
+
+pub fn get_collection_config(conn: &Connection, name: &str) -> Result<CollectionConfig> {
+    check_name(name)?;
+    let config: Option<String> = conn
+        .query_row(
+            "SELECT config FROM collections WHERE name = ?1",
+            params![name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match config {
+        Some(config) => Ok(serde_json::from_str(&config)?),
+        None => Err(Error::CollectionNotFound(name.to_string())),
+    }
+}
+
+/// The collection `name` refers to: the target of the alias `name` if
+/// there is one, otherwise `name` itself.
+pub fn resolve_alias(conn: &Connection, name: &str) -> Result<String> {
+    let collection: Option<String> = conn
+        .prepare_cached("SELECT collection FROM aliases WHERE name = ?1")?
+        .query_row(params![name], |row| row.get(0))
+        .optional()?;
+    Ok(collection.unwrap_or_else(|| name.to_string()))
+}
+
+pub fn list_aliases(conn: &Connection) -> Result<Vec<AliasDescription>> {
+    let mut stmt = conn.prepare("SELECT name, collection FROM aliases ORDER BY name")?;
+    let aliases = stmt
+        .query_map([], |row| {
+            Ok(AliasDescription {
+                alias_name: row.get(0)?,
+                collection_name: row.get(1)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(aliases)
+}
+
+/// Applies `actions` in order in one transaction: either all of them take
+/// effect or, when one fails, none does.
+pub fn update_aliases(conn: &Connection, actions: &[AliasOperation]) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for action in actions {
+        match action {
+            AliasOperation::CreateAlias {
+                collection_name,
+                alias_name,
+            } => {
+                get_collection_config(&tx, collection_name)?;
+                create_alias(&tx, alias_name, collection_name)?;
+            }
+            AliasOperation::DeleteAlias { alias_name } => {
+                delete_alias(&tx, alias_name)?;
+            }
+            AliasOperation::RenameAlias {
+                old_alias_name,
+                new_alias_name,
+            } => {
+                let collection = delete_alias(&tx, old_alias_name)?;
+                create_alias(&tx, new_alias_name, &collection)?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Aliases share the namespace of collections, so a name never means both.
+fn create_alias(conn: &Connection, alias: &str, collection: &str) -> Result<()> {
+    check_identifier("alias name", alias)?;
+    match get_collection_config(conn, alias) {
+        Ok(_) => return Err(Error::CollectionExists(alias.to_string())),
+        Err(Error::CollectionNotFound(_)) => {}
+        Err(e) => return Err(e),
+    }
+    conn.execute(
+        "INSERT OR REPLACE INTO aliases (name, collection) VALUES (?1, ?2)",
+        params![alias, collection],
+    )?;
+    Ok(())
+}
+
+/// Returns the collection the alias pointed at.
+fn delete_alias(conn: &Connection, alias: &str) -> Result<String> {
+    conn.query_row(
+        "DELETE FROM aliases WHERE name = ?1 RETURNING collection",
+        params![alias],
+        |row| row.get(0),
+    )
+    .optional()?
+    .ok_or_else(|| Error::AliasNotFound(alias.to_string()))
+}
+
+/// One vector space of a collection: a column of the vss0 table and, for
+/// indexes that keep the originals, a column of `vss_{name}_vectors`.
+#[derive(Debug, Clone, Copy)]
+struct VectorColumn<'a> {
+    name: Option<&'a str>,
+    size: usize,
+}
+
+impl VectorColumn<'_> {
+    /// Collections with a single unnamed vector keep the `point` and
+    /// `vector` columns they always had.
+    fn index_column(&self) -> String {
+        match self.name {
+            None => "point".to_string(),
+            Some(name) => format!("v_{}", name),
+        }
+    }
+
+    fn original_column(&self) -> String {
+        match self.name {
+            None => "vector".to_string(),
+            Some(name) => format!("v_{}", name),
+        }
+    }
+}
+
+fn vector_columns(config: &CollectionConfig) -> Vec<VectorColumn<'_>> {
+    config
+        .vectors
+        .params()
+        .into_iter()
+        .map(|(name, size)| VectorColumn { name, size })
+        .collect()
+}
+
+fn check_dimension(column: &VectorColumn, vector: &[f32]) -> Result<()> {
+    if vector.len() != column.size {
+        return Err(Error::DimensionMismatch {
+            expected: column.size,
+            got: vector.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Points carry every dense vector of the collection, each with its
+/// dimension. Sparse vectors are optional.
+fn check_point_vectors(config: &CollectionConfig, vectors: &VectorStruct) -> Result<()> {
+    let columns = vector_columns(config);
+    match vectors {
+        VectorStruct::Single(_) if !matches!(columns[..], [VectorColumn { name: None, .. }]) => {
+            return Err(Error::InvalidArgument(
+                "collection has named vectors, the point must name each of them".to_string(),
+            ))
+        }
+        VectorStruct::Single(_) => {}
+        VectorStruct::Named(named) => {
+            for (name, vector) in named {
+                check_named_vector(config, &columns, name, vector)?;
+            }
+        }
+    }
+    for column in &columns {
+        match vectors.get(column.name) {
+            Some(vector) => check_dimension(column, vector)?,
+            None => {
+                return Err(Error::InvalidArgument(format!(
+                    "vector `{}` is missing",
+                    column.name.unwrap_or_default()
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the collection has a vector `name` of this kind and size.
+fn check_named_vector(
+    config: &CollectionConfig,
+    columns: &[VectorColumn],
+    name: &str,
+    vector: &Vector,
+) -> Result<()> {
+    match vector {
+        Vector::Dense(dense) => {
+            if config.multivectors.contains_key(name) {
+                return Err(Error::InvalidArgument(format!(
+                    "vector `{}` is a multivector, it takes a list of vectors",
+                    name
+                )));
+            }
+            match columns.iter().find(|c| c.name.unwrap_or_default() == name) {
+                Some(column) => check_dimension(column, dense),
+                None => Err(unknown_vector(name)),
+            }
+        }
+        Vector::Sparse(sparse) => {
+            if !config.sparse_vectors.contains_key(name) {
+                return Err(unknown_vector(name));
+            }
+            check_sparse(sparse)
+        }
+        Vector::Multi(tokens) => {
+            let Some(params) = config.multivectors.get(name) else {
+                return Err(unknown_vector(name));
+            };
+            check_tokens(params.size, tokens)
+        }
+    }
+}
+
+fn check_sparse(vector: &SparseVector) -> Result<()> {
+    if vector.indices.len() != vector.values.len() {
+        return Err(Error::InvalidArgument(format!(
+            "sparse vector has {} indices but {} values",
+            vector.indices.len(),
+            vector.values.len()
+        )));
+    }
+    if vector.values.iter().any(|v| !v.is_finite()) {
+        return Err(Error::InvalidArgument(
+            "sparse vector values must be finite".to_string(),
+        ));
+    }
+    let mut indices = vector.indices.clone();
+    indices.sort_unstable();
+    if indices.windows(2).any(|w| w[0] == w[1]) {
+        return Err(Error::InvalidArgument(
+            "sparse vector indices must be unique".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn check_tokens(size: usize, tokens: &[Vec<f32>]) -> Result<()> {
+    for token in tokens {
+        if token.len() != size {
+            return Err(Error::DimensionMismatch {
+                expected: size,
+                got: token.len(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The flat vss0 table holding the tokens of multivector `vector`, and the
+/// table mapping its rowids to points and keeping the original tokens.
+fn multivector_tables(name: &str, vector: &str) -> (String, String) {
+    let index = format!("vss_{}_mv_{}", name, vector);
+    let tokens = format!("{}_tokens", index);
+    (index, tokens)
+}
+
+/// The vss0 table of the dense vectors, see
+/// [`CollectionConfig::index_generation`].
+fn index_table(name: &str, config: &CollectionConfig) -> String {
+    match config.index_generation {
+        0 => format!("vss_{}", name),
+        generation => format!("vss_{}_g{}", name, generation),
+    }
+}
+
+fn unknown_vector(name: &str) -> Error {
+    Error::InvalidArgument(format!("Not existing vector name error: {}", name))
+}
+
+/// Resolves the column a query vector searches and checks its dimension.
+fn query_column<'a>(
+    config: &'a CollectionConfig,
+    vector: &NamedVectorStruct,
+) -> Result<VectorColumn<'a>> {
+    let column = column_by_name(config, vector.name())?;
+    check_dimension(&column, vector.vector())?;
+    Ok(column)
+}
+
+fn column_by_name<'a>(
+    config: &'a CollectionConfig,
+    name: Option<&str>,
+) -> Result<VectorColumn<'a>> {
+    match vector_columns(config).into_iter().find(|c| c.name == name) {
+        Some(column) => Ok(column),
+        None => match name {
+            Some(name) => Err(unknown_vector(name)),
+            None => Err(Error::InvalidArgument(
+                "collection has named vectors, the query must name one".to_string(),
+            )),
+        },
+    }
+}
+
+/// Cheap readiness probe: checks that the vss extensions registered by
+/// [`init`] are loaded on this connection and that the main database accepts
+/// writes. The probe write is rolled back, so nothing is persisted.
+pub fn check_ready(conn: &Connection) -> Result<()> {
+    conn.query_row("SELECT vss_version(), vector_version()", [], |_| Ok(()))?;
+    if conn.is_readonly(rusqlite::DatabaseName::Main)? {
+        return Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_READONLY),
+            Some("database is opened read-only".to_string()),
+        )
+        .into());
+    }
+    // The savepoint is closed on the error path too, since the connection
+    // is shared and later writes would otherwise run inside it.
+    conn.execute_batch("SAVEPOINT readyz")?;
+    let probe = conn.execute_batch("CREATE TABLE readyz_probe (id INTEGER)");
+    conn.execute_batch("ROLLBACK TO readyz; RELEASE readyz")?;
+    probe?;
+    Ok(())
+}
+
+/// Copies the WAL back into the database file and truncates it. A no-op for
+/// connections that are not in WAL mode.
+pub fn checkpoint(conn: &Connection) -> Result<()> {
+    let (busy, log, checkpointed): (i64, i64, i64) =
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+    if busy != 0 {
+        log::warn!(
+            "WAL checkpoint was blocked, {} of {} frames checkpointed",
+            checkpointed,
+            log
+        );
+    }
+    Ok(())
+}
+
+pub fn create_collections(conn: &Connection, name: &str, size: usize) -> Result<()> {
+    create_collection_with_config(conn, name, &CollectionConfig::new(size))
+}
+
+pub fn create_collection_with_config(
+    conn: &Connection,
+    name: &str,
+    config: &CollectionConfig,
+) -> Result<()> {
+    check_name(name)?;
+    let columns = vector_columns(config);
+    if columns.is_empty() && config.multivectors.is_empty() {
+        return Err(Error::InvalidArgument(
+            "at least one named vector is required".to_string(),
+        ));
+    }
+    for column in &columns {
+        // Vector names become column names.
+        if let Some(name) = column.name {
+            check_identifier("vector name", name)?;
+        }
+        if column.size == 0 {
+            return Err(Error::InvalidArgument(
+                "vector size must be greater than 0".to_string(),
+            ));
+        }
+    }
+    for name in config.sparse_vectors.keys() {
+        check_identifier("vector name", name)?;
+        if columns.iter().any(|c| c.name == Some(name.as_str())) {
+            return Err(Error::InvalidArgument(format!(
+                "vector name `{}` is used for both a dense and a sparse vector",
+                name
+            )));
+        }
+    }
+    for (name, params) in &config.multivectors {
+        check_identifier("vector name", name)?;
+        if columns.iter().any(|c| c.name == Some(name.as_str()))
+            || config.sparse_vectors.contains_key(name)
+        {
+            return Err(Error::InvalidArgument(format!(
+                "vector name `{}` is used for both a multivector and another vector",
+                name
+            )));
+        }
+        if params.size == 0 {
+            return Err(Error::InvalidArgument(
+                "vector size must be greater than 0".to_string(),
+            ));
+        }
+    }
+    if columns.is_empty() && (config.index_factory.is_some() || config.is_quantized()) {
+        // Both configure the dense index, multivector tokens are always flat.
+        return Err(Error::InvalidArgument(
+            "index_factory and quantization_config need a dense vector".to_string(),
+        ));
+    }
+    if let Some(factory) = &config.index_factory {
+        check_index_factory(factory)?;
+        if config.quantization_config.is_some() {
+            return Err(Error::InvalidArgument(
+                "index_factory and quantization_config are mutually exclusive".to_string(),
+            ));
+        }
+    }
+    check_name_free(conn, name)?;
+
+    let config = CollectionConfig {
+        index_trained: false,
+        ..config.clone()
+    };
+    let tx = conn.unchecked_transaction()?;
+    tx.execute_batch(&data_tables(name, &config))?;
+    tx.execute_batch(&index_tables(name, &config))?;
+    save_collection_config(&tx, name, &config)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Fails with [`Error::CollectionExists`] when `name` is taken by a
+/// collection or an alias, and with [`Error::InvalidArgument`] when its
+/// tables would clash with those of another collection.
+fn check_name_free(conn: &Connection, name: &str) -> Result<()> {
+    check_name(name)?;
+    match get_collection_config(conn, name) {
+        Ok(_) => return Err(Error::CollectionExists(name.to_string())),
+        Err(Error::CollectionNotFound(_)) => {}
+        Err(e) => return Err(e),
+    }
+    if resolve_alias(conn, name)? != name {
+        return Err(Error::CollectionExists(name.to_string()));
+    }
+    for other in collection_names(conn)? {
+        if is_internal_name(name, &other) || is_internal_name(&other, name) {
+            return Err(Error::InvalidArgument(format!(
+                "collection name `{}` clashes with the tables of collection `{}`",
+                name, other
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The vss0 tables of a collection: the dense index and one per multivector.
+fn index_tables(name: &str, config: &CollectionConfig) -> String {
+    let columns = vector_columns(config);
+    let factory = config.index_factory();
+    let index_columns = columns
+        .iter()
+        .map(|c| format!("{}({}) factory=\"{}\"", c.index_column(), c.size, factory))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut sql = String::new();
+    if !columns.is_empty() {
+        sql.push_str(&format!(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS {} USING vss0({});",
+            index_table(name, config),
+            index_columns
+        ));
+    }
+    for (vector, params) in &config.multivectors {
+        let (index, _) = multivector_tables(name, vector);
+        sql.push_str(&format!(
+            r#"CREATE VIRTUAL TABLE IF NOT EXISTS {} USING vss0(token({}) factory="Flat,IDMap2");"#,
+            index, params.size
+        ));
+    }
+    sql
+}
+
+/// Every other table of a collection, with their indexes. Payload field
+/// indexes are created separately.
+fn data_tables(name: &str, config: &CollectionConfig) -> String {
+    let columns = vector_columns(config);
+    let mut sql = format!(
+        "CREATE TABLE IF NOT EXISTS vss_{}_payload (rowid INTEGER PRIMARY KEY, payload TEXT);",
+        name
     );
-    conn.execute_batch(sql.as_str())
+    if config.stores_original_vectors() {
+        // A trained index only holds approximations, keep the originals
+        // for training, rescoring and for returning vectors to clients.
+        let original_columns = columns
+            .iter()
+            .map(|c| format!(", {} BLOB NOT NULL", c.original_column()))
+            .collect::<String>();
+        sql.push_str(&format!(
+            "CREATE TABLE IF NOT EXISTS vss_{}_vectors (rowid INTEGER PRIMARY KEY{});",
+            name, original_columns
+        ));
+    }
+    if !config.sparse_vectors.is_empty() {
+        // An inverted index: one row per non-zero dimension, clustered so
+        // that a query dimension reads a single posting list.
+        sql.push_str(&format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS vss_{}_sparse (
+                vector TEXT NOT NULL, dim INTEGER NOT NULL, point_id INTEGER NOT NULL, value REAL NOT NULL,
+                PRIMARY KEY (vector, dim, point_id)
+            ) WITHOUT ROWID;
+            CREATE INDEX IF NOT EXISTS vss_{}_sparse_point ON vss_{}_sparse (point_id);
+            "#,
+            name, name, name
+        ));
+    }
+    for vector in config.multivectors.keys() {
+        let (_, tokens) = multivector_tables(name, vector);
+        sql.push_str(&format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {} (
+                rowid INTEGER PRIMARY KEY, point_id INTEGER NOT NULL, vector BLOB NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS {}_point ON {} (point_id);
+            "#,
+            tokens, tokens, tokens
+        ));
+    }
+    sql
+}
+
+/// The factory string ends up inside the vss0 declaration, so only the
+/// characters FAISS factory descriptions use are allowed.
+fn check_index_factory(factory: &str) -> Result<()> {
+    if factory.is_empty()
+        || !factory
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == ',' || c == '_')
+    {
+        return Err(Error::InvalidArgument(format!(
+            "index_factory `{}` must be a FAISS factory string such as `IVF1024,PQ64`",
+            factory
+        )));
+    }
+    Ok(())
 }
 
-pub fn get_collections_info(conn: &Connection, name: &str) -> rusqlite::Result<CollectionsInfo> {
+/// Number of points in the existing collection `name`. Points of an
+/// untrained index are not in vss0 yet, but every point has a payload row.
+pub fn points_count(conn: &Connection, name: &str) -> Result<u64> {
     let sql = format!(
         r#"
-        SELECT COUNT(*) FROM vss_{};
+        SELECT COUNT(*) FROM vss_{}_payload;
         "#,
         name
     );
-    let mut stmt = conn.prepare(sql.as_str())?;
-    let count: u64 = stmt.query_row([], |row| row.get(0)).unwrap();
-    Ok(CollectionsInfo {
+    Ok(conn.query_row(sql.as_str(), [], |row| row.get(0))?)
+}
+
+pub fn get_collections_info(conn: &Connection, name: &str) -> Result<CollectionInfo> {
+    let config = get_collection_config(conn, name)?;
+    let count = points_count(conn, name)?;
+    let quantization = match &config.quantization_config {
+        Some(QuantizationConfig::Scalar(scalar)) => {
+            let dimensions = vector_columns(&config)
+                .iter()
+                .map(|c| c.size)
+                .sum::<usize>();
+            let original_vector_bytes = dimensions * 4;
+            let quantized_vector_bytes = dimensions;
+            Some(QuantizationInfo {
+                kind: scalar.kind,
+                original_vector_bytes,
+                quantized_vector_bytes,
+                compression_ratio: original_vector_bytes as f32 / quantized_vector_bytes as f32,
+                index_bytes: count * quantized_vector_bytes as u64,
+                original_vectors_bytes: count * original_vector_bytes as u64,
+            })
+        }
+        None => None,
+    };
+    let mut payload_schema = BTreeMap::new();
+    for (key, data_type) in &config.payload_schema {
+        let sql = if *data_type == PayloadSchemaType::Geo {
+            format!("SELECT COUNT(*) FROM {}", geo_index_name(name, key))
+        } else {
+            format!(
+                "SELECT COUNT(*) FROM vss_{}_payload WHERE json_extract(payload, {}) IS NOT NULL",
+                name,
+                filter::json_path(key)?
+            )
+        };
+        let points = conn.query_row(&sql, [], |row| row.get(0))?;
+        payload_schema.insert(
+            key.clone(),
+            PayloadIndexInfo {
+                data_type: *data_type,
+                points,
+            },
+        );
+    }
+    let rebuild = config.rebuild.as_ref().map(|rebuild| RebuildInfo {
+        index_factory: config.rebuilt(rebuild).index_factory(),
+        points_copied: rebuild.points_copied,
+        points_count: count,
+    });
+    Ok(CollectionInfo {
         points_count: count,
+        quantization,
+        payload_schema,
+        rebuild,
     })
 }
 
+#[cfg(test)]
+fn test_conn() -> Connection {
+    init();
+    let conn = Connection::open_in_memory().unwrap();
+    init_schema(&conn).unwrap();
+    conn
+}
+
 #[test]
 fn test_collections() {
-    init();
-    let conn = rusqlite::Connection::open_in_memory().unwrap();
+    let conn = test_conn();
     create_collections(&conn, "test_vss", 4).unwrap();
     let r = get_collections_info(&conn, "test_vss").unwrap();
     assert_eq!(r.points_count, 0);
 }
 
-fn blob_to_vector(blob: &[u8]) -> Vec<f32> {
-    unsafe {
-        std::slice::from_raw_parts(blob.as_ptr() as *const f32, blob.len() / size_of::<f32>())
-            .to_vec()
+#[test]
+fn test_check_ready() {
+    let conn = test_conn();
+    check_ready(&conn).unwrap();
+    let exists: bool = conn
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE name = 'readyz_probe')",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert!(!exists);
+
+    // A failing probe leaves no transaction open behind it.
+    conn.execute_batch("CREATE TABLE readyz_probe (id INTEGER)")
+        .unwrap();
+    assert!(check_ready(&conn).is_err());
+    assert!(conn.is_autocommit());
+}
+
+/// Vectors are stored as packed little-endian `f32`s, the layout
+/// `vector_from_raw` / `vector_to_raw` use on little-endian hosts.
+fn blob_to_vector(blob: &[u8]) -> Result<Vec<f32>> {
+    let chunks = blob.chunks_exact(4);
+    if !chunks.remainder().is_empty() {
+        return Err(Error::InvalidVectorBlob { len: blob.len() });
     }
+    Ok(chunks
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
 }
 
 fn vector_to_blob(vector: &[f32]) -> Vec<u8> {
-    unsafe {
-        std::slice::from_raw_parts(
-            vector.as_ptr() as *const u8,
-            vector.len() * size_of::<f32>(),
-        )
-        .to_vec()
-    }
+    vector.iter().flat_map(|v| v.to_le_bytes()).collect()
 }
 
-pub fn add_point(conn: &Connection, name: &str, points: &[Point]) -> rusqlite::Result<Vec<u64>> {
-    let mut check_stmt = conn
-        .prepare(&format!("SELECT rowid FROM vss_{} WHERE rowid = ?1", name))
-        .unwrap();
+#[test]
+fn test_vector_blob_codec() {
+    let vector = vec![0.05, -0.61, f32::MAX, f32::MIN_POSITIVE, 0.0];
+    let blob = vector_to_blob(&vector);
+    assert_eq!(blob.len(), 20);
+    assert_eq!(&blob[4..8], &(-0.61f32).to_le_bytes());
+    assert_eq!(blob_to_vector(&blob).unwrap(), vector);
 
-    let mut vector_stmt = conn
-        .prepare(&format!(
-            "INSERT INTO vss_{}(rowid,point) VALUES (?1, vector_from_raw(?2))",
-            name
-        ))
-        .unwrap();
+    // A slice starting one byte into the buffer is never 4-byte aligned.
+    let mut buf = vec![0u8];
+    buf.extend_from_slice(&blob);
+    assert_eq!(blob_to_vector(&buf[1..]).unwrap(), vector);
 
-    let mut payload_stmt = conn
-        .prepare(&format!(
-            "INSERT OR REPLACE INTO vss_{}_payload(rowid,payload) VALUES (?1, ?2)",
-            name
-        ))
-        .unwrap();
+    assert!(blob_to_vector(&[]).unwrap().is_empty());
+    assert!(matches!(
+        blob_to_vector(&blob[..7]),
+        Err(Error::InvalidVectorBlob { len: 7 })
+    ));
+}
 
-    let mut success_id = vec![];
+pub fn add_point(conn: &Connection, name: &str, points: &[Point]) -> Result<Vec<u64>> {
+    let tx = conn.unchecked_transaction()?;
+    let ids = upsert_points(&tx, name, points)?;
+    tx.commit()?;
+    Ok(ids)
+}
 
+/// [`add_point`] inside the caller's transaction.
+fn upsert_points(tx: &Connection, name: &str, points: &[Point]) -> Result<Vec<u64>> {
+    let config = get_collection_config(tx, name)?;
     for point in points {
-        if check_stmt.exists(params![point.id])? {
-            delete_points(conn, name, vec![point.id])?;
+        check_id(point.id)?;
+        check_point_vectors(&config, &point.vector)?;
+    }
+    let columns = vector_columns(&config);
+    let index_columns = columns
+        .iter()
+        .map(|c| c.index_column())
+        .collect::<Vec<_>>()
+        .join(",");
+    let from_raw = vec!["vector_from_raw(?)"; columns.len()].join(",");
+    let blobs = |point: &Point| {
+        columns
+            .iter()
+            .map(|c| Value::Blob(vector_to_blob(point.vector.get(c.name).unwrap_or_default())))
+            .collect::<Vec<_>>()
+    };
+
+    // Points a rebuild has already copied are written to the new index too,
+    // once it is trained.
+    let rebuild_index = config
+        .rebuild
+        .as_ref()
+        .map(|rebuild| config.rebuilt(rebuild))
+        .filter(|rebuilt| !rebuilt.needs_training())
+        .zip(config.rebuild.as_ref().and_then(|r| r.copied_up_to));
+
+    let trains_now =
+        config.needs_training() && config.trains_on_first_batch() && !points.is_empty();
+    if trains_now {
+        // vss0 trains before it adds vectors when both are pending at commit,
+        // so the first batch doubles as the training set.
+        let mut train_stmt = tx.prepare(&format!(
+            "INSERT INTO {}(operation,{}) VALUES ('training', {})",
+            index_table(name, &config),
+            index_columns,
+            from_raw
+        ))?;
+        for point in points {
+            train_stmt.execute(params_from_iter(blobs(point)))?;
         }
+        let trained = CollectionConfig {
+            index_trained: true,
+            ..config.clone()
+        };
+        save_collection_config(tx, name, &trained)?;
+    }
+    // Until the index is trained the originals are all there is; training
+    // adds them to vss0.
+    let indexes_vectors = (trains_now || !config.needs_training()) && !columns.is_empty();
+    {
+        let mut original_stmt = if config.stores_original_vectors() {
+            Some(tx.prepare(&format!(
+                "INSERT OR REPLACE INTO vss_{}_vectors(rowid,{}) VALUES (?{})",
+                name,
+                columns
+                    .iter()
+                    .map(|c| c.original_column())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                ", ?".repeat(columns.len())
+            ))?)
+        } else {
+            None
+        };
+
+        let mut index_stmts = if indexes_vectors {
+            Some((
+                tx.prepare(&format!(
+                    "DELETE FROM {} WHERE rowid = ?1",
+                    index_table(name, &config)
+                ))?,
+                tx.prepare(&format!(
+                    "INSERT INTO {}(rowid,{}) VALUES (?, {})",
+                    index_table(name, &config),
+                    index_columns,
+                    from_raw
+                ))?,
+            ))
+        } else {
+            None
+        };
+
+        let mut rebuild_stmts = match &rebuild_index {
+            Some((rebuilt, copied_up_to)) => Some((
+                *copied_up_to,
+                tx.prepare(&format!(
+                    "DELETE FROM {} WHERE rowid = ?1",
+                    index_table(name, rebuilt)
+                ))?,
+                tx.prepare(&format!(
+                    "INSERT INTO {}(rowid,{}) VALUES (?, {})",
+                    index_table(name, rebuilt),
+                    index_columns,
+                    from_raw
+                ))?,
+            )),
+            None => None,
+        };
+
+        let mut payload_stmt = tx.prepare(&format!(
+            "INSERT OR REPLACE INTO vss_{}_payload(rowid,payload) VALUES (?1, ?2)",
+            name
+        ))?;
+
+        let mut sparse_stmts = if config.sparse_vectors.is_empty() {
+            None
+        } else {
+            Some((
+                tx.prepare(&format!(
+                    "DELETE FROM vss_{}_sparse WHERE point_id = ?1",
+                    name
+                ))?,
+                tx.prepare(&format!(
+                    "INSERT INTO vss_{}_sparse(vector,dim,point_id,value) VALUES (?1, ?2, ?3, ?4)",
+                    name
+                ))?,
+            ))
+        };
+
+        let last_index = points
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p.id, i))
+            .collect::<HashMap<u64, usize>>();
+        for (i, point) in points.iter().enumerate() {
+            // Repeated ids in one request: the last occurrence wins.
+            if last_index[&point.id] != i {
+                continue;
+            }
+            let mut values = vec![Value::Integer(point.id as i64)];
+            values.extend(blobs(point));
+            if let Some((delete_stmt, vector_stmt)) = index_stmts.as_mut() {
+                // vss0 rejects duplicate rowids, so an upsert replaces the vector.
+                delete_stmt.execute(params![point.id])?;
+                vector_stmt.execute(params_from_iter(&values))?;
+            }
+            if let Some((copied_up_to, delete_stmt, vector_stmt)) = rebuild_stmts.as_mut() {
+                if point.id <= *copied_up_to {
+                    delete_stmt.execute(params![point.id])?;
+                    vector_stmt.execute(params_from_iter(&values))?;
+                }
+            }
+            if let Some(stmt) = original_stmt.as_mut() {
+                stmt.execute(params_from_iter(&values))?;
+            }
 
-        let raw = vector_to_blob(&point.vector);
-        vector_stmt.execute(params![point.id as i64, raw])?;
+            if let Some((delete_sparse, insert_sparse)) = sparse_stmts.as_mut() {
+                delete_sparse.execute(params![point.id])?;
+                for vector_name in config.sparse_vectors.keys() {
+                    let Some(sparse) = point.vector.get_sparse(vector_name) else {
+                        continue;
+                    };
+                    for (dim, value) in sparse.indices.iter().zip(&sparse.values) {
+                        insert_sparse.execute(params![vector_name, dim, point.id, value])?;
+                    }
+                }
+            }
+
+            for vector_name in config.multivectors.keys() {
+                let (index, tokens) = multivector_tables(name, vector_name);
+                delete_tokens(tx, name, vector_name, &point.id.to_string())?;
+                let mut token_stmt = tx.prepare_cached(&format!(
+                    "INSERT INTO {}(point_id,vector) VALUES (?1, ?2)",
+                    tokens
+                ))?;
+                let mut index_stmt = tx.prepare_cached(&format!(
+                    "INSERT INTO {}(rowid,token) VALUES (?1, vector_from_raw(?2))",
+                    index
+                ))?;
+                for token in point.vector.get_multi(vector_name).unwrap_or_default() {
+                    let blob = vector_to_blob(token);
+                    token_stmt.execute(params![point.id, blob])?;
+                    index_stmt.execute(params![tx.last_insert_rowid(), blob])?;
+                }
+            }
 
-        let payload = serde_json::to_string(&point.payload).unwrap();
-        payload_stmt.execute(params![point.id as i64, payload])?;
+            for key in geo_keys(&config) {
+                index_location(tx, name, key, point.id, point.payload.as_ref())?;
+            }
 
-        success_id.push(point.id);
+            let payload = serde_json::to_string(&point.payload)?;
+            payload_stmt.execute(params![point.id, payload])?;
+        }
     }
 
-    Ok(success_id)
+    Ok(points.iter().map(|p| p.id).collect())
 }
 
-pub fn get_points(
-    conn: &rusqlite::Connection,
-    name: &str,
-    ids: Vec<u64>,
-) -> rusqlite::Result<Vec<Point>> {
-    let ids = ids
-        .iter()
-        .map(|id| id.to_string())
-        .collect::<Vec<String>>()
-        .join(",");
-
-    let point_sql = format!(
+/// Removes the tokens of the points in `ids`, a comma separated list as
+/// built by [`id_list`], from the tokens table and its vss0 table.
+fn delete_tokens(conn: &Connection, name: &str, vector: &str, ids: &str) -> Result<()> {
+    let (index, tokens) = multivector_tables(name, vector);
+    let rowids = conn
+        .prepare(&format!(
+            "SELECT rowid FROM {} WHERE point_id in ({})",
+            tokens, ids
+        ))?
+        .query_map([], |row| row.get::<_, i64>(0))?
+        .map(|rowid| rowid.map(|rowid| rowid.to_string()))
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if rowids.is_empty() {
+        return Ok(());
+    }
+    conn.execute_batch(&format!(
         r#"
-        SELECT rowid,vector_to_raw(point) FROM vss_{} WHERE rowid in ({});
+        DELETE FROM {} WHERE rowid in ({});
+        DELETE FROM {} WHERE point_id in ({});
         "#,
-        name, ids
-    );
+        index,
+        rowids.join(","),
+        tokens,
+        ids
+    ))?;
+    Ok(())
+}
+
+/// Selects `rowid` and every vector as raw blobs, named after
+/// [`VectorColumn::original_column`]. Collections with a trained index keep
+/// the exact vectors outside of vss0, which only returns reconstructions.
+fn vector_source(config: &CollectionConfig, name: &str) -> String {
+    let columns = vector_columns(config);
+    if columns.is_empty() {
+        // Multivector-only collection, every point has a payload row.
+        format!("SELECT rowid FROM vss_{}_payload", name)
+    } else if config.stores_original_vectors() {
+        let originals = columns
+            .iter()
+            .map(|c| c.original_column())
+            .collect::<Vec<_>>()
+            .join(",");
+        format!("SELECT rowid,{} FROM vss_{}_vectors", originals, name)
+    } else {
+        let raw = columns
+            .iter()
+            .map(|c| {
+                format!(
+                    "vector_to_raw({}) AS {}",
+                    c.index_column(),
+                    c.original_column()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        format!("SELECT rowid,{} FROM {}", raw, index_table(name, config))
+    }
+}
+
+/// Selects `(rowid, raw vector)` of a single vector space.
+fn column_source(config: &CollectionConfig, name: &str, column: &VectorColumn) -> String {
+    format!(
+        "SELECT rowid,{} FROM ({})",
+        column.original_column(),
+        vector_source(config, name)
+    )
+}
+
+/// Reads the vectors selected by [`vector_source`] from `row`.
+fn row_vectors(row: &rusqlite::Row, columns: &[VectorColumn]) -> Result<VectorStruct> {
+    if let [VectorColumn { name: None, .. }] = columns {
+        return blob_to_vector(&row.get::<_, Vec<u8>>(1)?).map(VectorStruct::Single);
+    }
+    let mut vectors = BTreeMap::new();
+    for (i, column) in columns.iter().enumerate() {
+        let vector = blob_to_vector(&row.get::<_, Vec<u8>>(i + 1)?)?;
+        vectors.insert(column.name.unwrap_or_default().to_string(), vector.into());
+    }
+    Ok(VectorStruct::Named(vectors))
+}
+
+pub fn get_points(conn: &Connection, name: &str, ids: Vec<u64>) -> Result<Vec<Point>> {
+    let config = get_collection_config(conn, name)?;
+    let ids = id_list(&ids)?;
+
+    let mut vectors = get_vectors(conn, &config, name, &ids)?
+        .into_iter()
+        .collect::<Vec<_>>();
+    vectors.sort_by_key(|(id, _)| *id);
+    let mut payloads = get_payloads(conn, name, &ids)?;
+
+    Ok(vectors
+        .into_iter()
+        .map(|(id, vector)| Point {
+            id,
+            vector,
+            payload: payloads.remove(&id).flatten(),
+        })
+        .collect())
+}
 
+/// `ids` is a comma separated list as built by [`id_list`].
+fn get_payloads(conn: &Connection, name: &str, ids: &str) -> Result<HashMap<u64, Option<Payload>>> {
     let payload_sql = format!(
         r#"
-        SELECT * FROM vss_{}_payload WHERE rowid in ({});
+        SELECT rowid,payload FROM vss_{}_payload WHERE rowid in ({});
         "#,
         name, ids
     );
-
-    let mut point_stmt = conn.prepare(point_sql.as_str()).unwrap();
-    let mut payload_stmt = conn.prepare(payload_sql.as_str()).unwrap();
+    let mut payload_stmt = conn.prepare(payload_sql.as_str())?;
+    let rows = payload_stmt.query_map(params![], |row| {
+        let id: u64 = row.get(0)?;
+        let payload_str: Option<String> = row.get(1)?;
+        Ok((id, payload_str))
+    })?;
 
     let mut map = HashMap::new();
+    for row in rows {
+        let (id, payload_str) = row?;
+        map.insert(id, parse_payload(payload_str.as_deref()));
+    }
+    Ok(map)
+}
 
-    let vector_r = point_stmt.query_map(params![], |row| {
-        let id: u64 = row.get(0)?;
-        let vector_raw: Vec<u8> = row.get(1)?;
-        let vector: Vec<f32> = blob_to_vector(&vector_raw);
-        Ok((id, vector))
-    })?;
+fn parse_payload(payload_str: Option<&str>) -> Option<Payload> {
+    payload_str.and_then(|s| serde_json::from_str(s).unwrap_or_default())
+}
 
-    let payload_r = payload_stmt.query_map(params![], |row| {
-        let id: u64 = row.get(0)?;
-        let payload_str: String = row.get(1)?;
-        let payload: Option<serde_json::Map<String, serde_json::Value>> =
-            serde_json::from_str(&payload_str).unwrap_or_default();
-        Ok((id, payload))
-    })?;
+/// Point ids are stored as SQLite rowids, which are signed 64-bit.
+fn check_id(id: u64) -> Result<()> {
+    if id > i64::MAX as u64 {
+        return Err(Error::InvalidArgument(format!(
+            "point id {} is out of range, the maximum is {}",
+            id,
+            i64::MAX
+        )));
+    }
+    Ok(())
+}
 
-    for v in vector_r {
-        if let Ok((id, vector)) = v {
-            map.insert(
-                id,
-                Point {
-                    id,
-                    vector,
-                    payload: None,
-                },
-            );
-        }
+/// Formats ids for an `IN (...)` list. Ids are integers, so inlining is safe.
+fn id_list(ids: &[u64]) -> Result<String> {
+    for id in ids {
+        check_id(*id)?;
+    }
+    Ok(ids
+        .iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<String>>()
+        .join(","))
+}
+
+pub fn get_point(conn: &Connection, name: &str, id: u64) -> Result<Option<Point>> {
+    Ok(get_points(conn, name, vec![id])?.pop())
+}
+
+#[test]
+fn test_points_base() {
+    use serde_json::json;
+    let conn = test_conn();
+    create_collections(&conn, "test_vss", 4).unwrap();
+    let mut points = Vec::<Point>::new();
+    {
+        points.push(Point {
+            id: 1,
+            vector: vec![0.05, 0.61, 0.76, 0.74].into(),
+            payload: json!({"city": "Berlin"}).as_object().map(|m| m.to_owned()),
+        });
+        points.push(Point {
+            id: 2,
+            vector: vec![0.19, 0.81, 0.75, 0.11].into(),
+            payload: json!({"city": "London"}).as_object().map(|m| m.to_owned()),
+        });
+        points.push(Point {
+            id: 3,
+            vector: vec![0.36, 0.55, 0.47, 0.94].into(),
+            payload: json!({"city": "Moscow"}).as_object().map(|m| m.to_owned()),
+        });
+        points.push(Point {
+            id: 4,
+            vector: vec![0.18, 0.01, 0.85, 0.80].into(),
+            payload: json!({"city": "New York"})
+                .as_object()
+                .map(|m| m.to_owned()),
+        });
+        points.push(Point {
+            id: 5,
+            vector: vec![0.24, 0.18, 0.22, 0.44].into(),
+            payload: json!({"city": "Beijing"}).as_object().map(|m| m.to_owned()),
+        });
+        points.push(Point {
+            id: 6,
+            vector: vec![0.35, 0.08, 0.11, 0.44].into(),
+            payload: json!({"city": "Mumbai"}).as_object().map(|m| m.to_owned()),
+        });
+    }
+    let r = add_point(&conn, "test_vss", &points).unwrap();
+    assert_eq!(r, vec![1, 2, 3, 4, 5, 6]);
+
+    let r = add_point(&conn, "test_vss", &points[1..]).unwrap();
+    assert_eq!(r, vec![2, 3, 4, 5, 6]);
+
+    let mut r = get_points(&conn, "test_vss", vec![1, 2, 3]).unwrap();
+    assert_eq!(r.len(), 3);
+    r.sort_by_key(|p| p.id);
+    assert_eq!(r[0].payload, points[0].payload);
+    assert_eq!(r[1].payload, points[1].payload);
+    assert_eq!(r[2].payload, points[2].payload);
+
+    let r = get_point(&conn, "test_vss", 4).unwrap().unwrap();
+    assert_eq!(r.payload, points[3].payload);
+}
+
+/// Expression index on a payload key, shared by scroll ordering and
+/// filters on that key.
+fn payload_index_name(name: &str, key: &str) -> String {
+    format!("vss_{}_payload_{}", name, index_key(key))
+}
+
+/// R*Tree over the locations at a geo payload key, keyed by point id.
+fn geo_index_name(name: &str, key: &str) -> String {
+    format!("vss_{}_geo_{}", name, index_key(key))
+}
+
+/// Encodes a payload key for use in an identifier: `_` becomes `__` and `.`
+/// becomes `_d`, so `a.b` and `a__b` get distinct indexes.
+fn index_key(key: &str) -> String {
+    key.replace('_', "__").replace('.', "_d")
+}
+
+fn geo_keys(config: &CollectionConfig) -> impl Iterator<Item = &str> {
+    config
+        .payload_schema
+        .iter()
+        .filter(|(_, schema)| **schema == PayloadSchemaType::Geo)
+        .map(|(key, _)| key.as_str())
+}
+
+fn compile_filter(
+    name: &str,
+    config: &CollectionConfig,
+    filter: Option<&Filter>,
+) -> Result<Option<SqlFilter>> {
+    let geo_index = |key: &str| {
+        (config.payload_schema.get(key) == Some(&PayloadSchemaType::Geo))
+            .then(|| geo_index_name(name, key))
+    };
+    filter
+        .map(|filter| filter::compile(filter, &geo_index))
+        .transpose()
+}
+
+fn create_payload_index(conn: &Connection, name: &str, key: &str) -> Result<()> {
+    conn.execute_batch(&format!(
+        "CREATE INDEX IF NOT EXISTS {} ON vss_{}_payload (json_extract(payload, {}));",
+        payload_index_name(name, key),
+        name,
+        filter::json_path(key)?
+    ))?;
+    Ok(())
+}
+
+/// Indexes the payload field `key` and records it in the collection's
+/// payload schema. Filters on the field then search the index instead of
+/// parsing every payload.
+pub fn create_field_index(
+    conn: &Connection,
+    name: &str,
+    key: &str,
+    schema: PayloadSchemaType,
+) -> Result<()> {
+    let mut config = get_collection_config(conn, name)?;
+    filter::json_path(key)?;
+    if schema == PayloadSchemaType::Text {
+        return Err(Error::InvalidArgument(
+            "full-text indexes are not supported, use `keyword` to match whole values".to_string(),
+        ));
+    }
+    let tx = conn.unchecked_transaction()?;
+    drop_field_index(&tx, name, key)?;
+    if schema == PayloadSchemaType::Geo {
+        create_geo_index(&tx, name, key)?;
+    } else {
+        create_payload_index(&tx, name, key)?;
+    }
+    config.payload_schema.insert(key.to_string(), schema);
+    save_collection_config(&tx, name, &config)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Drops the index on `key`. Fields without one are left as they are.
+pub fn delete_field_index(conn: &Connection, name: &str, key: &str) -> Result<()> {
+    let mut config = get_collection_config(conn, name)?;
+    filter::json_path(key)?;
+    let tx = conn.unchecked_transaction()?;
+    drop_field_index(&tx, name, key)?;
+    if config.payload_schema.remove(key).is_some() {
+        save_collection_config(&tx, name, &config)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn drop_field_index(conn: &Connection, name: &str, key: &str) -> Result<()> {
+    conn.execute_batch(&format!(
+        "DROP INDEX IF EXISTS {}; DROP TABLE IF EXISTS {};",
+        payload_index_name(name, key),
+        geo_index_name(name, key)
+    ))?;
+    Ok(())
+}
+
+/// Creates the R*Tree of a geo key and fills it from the stored payloads.
+/// Upserts and deletes keep it in sync from then on.
+fn create_geo_index(conn: &Connection, name: &str, key: &str) -> Result<()> {
+    let index = geo_index_name(name, key);
+    let lat = filter::json_path(&format!("{}.lat", key))?;
+    let lon = filter::json_path(&format!("{}.lon", key))?;
+    conn.execute_batch(&format!(
+        r#"
+        CREATE VIRTUAL TABLE {} USING rtree(id, min_lat, max_lat, min_lon, max_lon);
+        INSERT INTO {} SELECT rowid, lat, lat, lon, lon FROM (
+            SELECT rowid, json_extract(payload, {}) AS lat, json_extract(payload, {}) AS lon
+            FROM vss_{}_payload
+            WHERE json_type(payload, {}) IN ('integer', 'real')
+              AND json_type(payload, {}) IN ('integer', 'real')
+        );
+        "#,
+        index, index, lat, lon, name, lat, lon
+    ))?;
+    Ok(())
+}
+
+/// Replaces the R*Tree entry of point `id` with the location at `key` in
+/// `payload`, if it has one.
+fn index_location(
+    conn: &Connection,
+    name: &str,
+    key: &str,
+    id: u64,
+    payload: Option<&Payload>,
+) -> Result<()> {
+    let index = geo_index_name(name, key);
+    conn.prepare_cached(&format!("DELETE FROM {} WHERE id = ?1", index))?
+        .execute(params![id])?;
+    let location = payload_value(payload, key).and_then(|location| {
+        Some((
+            location.get("lat")?.as_f64()?,
+            location.get("lon")?.as_f64()?,
+        ))
+    });
+    if let Some((lat, lon)) = location {
+        conn.prepare_cached(&format!(
+            "INSERT INTO {}(id, min_lat, max_lat, min_lon, max_lon) VALUES (?1, ?2, ?2, ?3, ?3)",
+            index
+        ))?
+        .execute(params![id, lat, lon])?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_field_index() {
+    use serde_json::json;
+
+    let conn = test_conn();
+    create_collections(&conn, "test_index", 2).unwrap();
+    let points = (0..20)
+        .map(|i| Point {
+            id: i,
+            vector: vec![i as f32, 0.0].into(),
+            payload: json!({"shop": {"city": if i < 15 { "Berlin" } else { "Paris" }}})
+                .as_object()
+                .map(|m| m.to_owned()),
+        })
+        .collect::<Vec<_>>();
+    add_point(&conn, "test_index", &points).unwrap();
+
+    create_field_index(&conn, "test_index", "shop.city", PayloadSchemaType::Keyword).unwrap();
+    let info = get_collections_info(&conn, "test_index").unwrap();
+    assert_eq!(
+        info.payload_schema["shop.city"],
+        PayloadIndexInfo {
+            data_type: PayloadSchemaType::Keyword,
+            points: 20
+        }
+    );
+
+    // The compiled filter reads the index instead of scanning payloads.
+    let filter = filter::compile(
+        &serde_json::from_value(
+            json!({"must": [{"key": "shop.city", "match": {"value": "Paris"}}]}),
+        )
+        .unwrap(),
+        &|_| None,
+    )
+    .unwrap();
+    let plan: String = conn
+        .query_row(
+            &format!(
+                "EXPLAIN QUERY PLAN SELECT p.rowid FROM vss_test_index_payload p WHERE {}",
+                filter.sql
+            ),
+            params_from_iter(filter.params),
+            |row| row.get(3),
+        )
+        .unwrap();
+    assert!(
+        plan.contains("USING INDEX vss_test_index_payload_shop_dcity"),
+        "{}",
+        plan
+    );
+
+    assert!(matches!(
+        create_field_index(&conn, "test_index", "shop city", PayloadSchemaType::Keyword),
+        Err(Error::InvalidFilter(_))
+    ));
+    assert!(matches!(
+        create_field_index(&conn, "test_index", "shop.city", PayloadSchemaType::Text),
+        Err(Error::InvalidArgument(_))
+    ));
+    let indexes = || -> u64 {
+        conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND tbl_name = 'vss_test_index_payload'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap()
+    };
+    // `shop__city` doesn't replace the index of `shop.city`.
+    create_field_index(
+        &conn,
+        "test_index",
+        "shop__city",
+        PayloadSchemaType::Keyword,
+    )
+    .unwrap();
+    assert_eq!(indexes(), 2);
+    delete_field_index(&conn, "test_index", "shop.city").unwrap();
+    let info = get_collections_info(&conn, "test_index").unwrap();
+    assert_eq!(
+        info.payload_schema.keys().collect::<Vec<_>>(),
+        vec!["shop__city"]
+    );
+    assert_eq!(indexes(), 1);
+    delete_field_index(&conn, "test_index", "shop__city").unwrap();
+    assert_eq!(indexes(), 0);
+}
+
+#[test]
+fn test_geo_filters() {
+    use serde_json::json;
+
+    let conn = test_conn();
+    create_collections(&conn, "test_geo", 2).unwrap();
+    let cities = [
+        (1, 52.5200, 13.4050),  // Berlin
+        (2, 52.3906, 13.0645),  // Potsdam
+        (3, 48.8566, 2.3522),   // Paris
+        (4, 40.7128, -74.0060), // New York
+    ];
+    let mut points = cities
+        .iter()
+        .map(|&(id, lat, lon)| Point {
+            id,
+            vector: vec![lat as f32, lon as f32].into(),
+            payload: json!({"location": {"lat": lat, "lon": lon}})
+                .as_object()
+                .map(|m| m.to_owned()),
+        })
+        .collect::<Vec<_>>();
+    points.push(Point {
+        id: 5,
+        vector: vec![0.0, 0.0].into(),
+        payload: json!({"location": "unknown"})
+            .as_object()
+            .map(|m| m.to_owned()),
+    });
+    add_point(&conn, "test_geo", &points).unwrap();
+
+    let matching = |mut condition: serde_json::Value| {
+        condition["key"] = json!("location");
+        let request = ScrollRequest {
+            filter: Some(serde_json::from_value(json!({ "must": [condition] })).unwrap()),
+            ..Default::default()
+        };
+        scroll(&conn, "test_geo", &request)
+            .unwrap()
+            .points
+            .iter()
+            .map(|p| p.id)
+            .collect::<Vec<_>>()
+    };
+    let check = || {
+        assert_eq!(
+            matching(json!({"geo_radius": {
+                "center": {"lat": 52.52, "lon": 13.405},
+                "radius": 35_000.0
+            }})),
+            vec![1, 2]
+        );
+        assert_eq!(
+            matching(json!({"geo_bounding_box": {
+                "top_left": {"lat": 55.0, "lon": 0.0},
+                "bottom_right": {"lat": 45.0, "lon": 14.0}
+            }})),
+            vec![1, 2, 3]
+        );
+        // Crossing the antimeridian, the box spans the Americas and Asia.
+        assert_eq!(
+            matching(json!({"geo_bounding_box": {
+                "top_left": {"lat": 60.0, "lon": 100.0},
+                "bottom_right": {"lat": 30.0, "lon": -70.0}
+            }})),
+            vec![4]
+        );
+        assert_eq!(
+            matching(json!({"geo_polygon": {
+                "exterior": {"points": [
+                    {"lat": 47.0, "lon": 0.0},
+                    {"lat": 54.0, "lon": 0.0},
+                    {"lat": 54.0, "lon": 14.0},
+                    {"lat": 47.0, "lon": 14.0}
+                ]},
+                "interiors": [{"points": [
+                    {"lat": 52.3, "lon": 12.9},
+                    {"lat": 52.45, "lon": 12.9},
+                    {"lat": 52.45, "lon": 13.2},
+                    {"lat": 52.3, "lon": 13.2}
+                ]}]
+            }})),
+            vec![1, 3]
+        );
+    };
+    check();
+
+    create_field_index(&conn, "test_geo", "location", PayloadSchemaType::Geo).unwrap();
+    let info = get_collections_info(&conn, "test_geo").unwrap();
+    assert_eq!(info.payload_schema["location"].points, 4);
+    let indexed: u64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM vss_test_geo_geo_location",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(indexed, 4);
+    check();
+
+    // Upserts and deletes keep the R*Tree in sync.
+    add_point(
+        &conn,
+        "test_geo",
+        &[Point {
+            id: 2,
+            vector: vec![0.0, 0.0].into(),
+            payload: None,
+        }],
+    )
+    .unwrap();
+    delete_points(&conn, "test_geo", vec![3]).unwrap();
+    let indexed: Vec<u64> = conn
+        .prepare("SELECT id FROM vss_test_geo_geo_location ORDER BY id")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap();
+    assert_eq!(indexed, vec![1, 4]);
+
+    assert!(matches!(
+        scroll(
+            &conn,
+            "test_geo",
+            &ScrollRequest {
+                filter: Some(
+                    serde_json::from_value(json!({"must": [{"key": "location", "geo_radius": {
+                        "center": {"lat": 91.0, "lon": 0.0},
+                        "radius": 1.0
+                    }}]}))
+                    .unwrap()
+                ),
+                ..Default::default()
+            }
+        ),
+        Err(Error::InvalidFilter(_))
+    ));
+    delete_collection(&conn, "test_geo").unwrap();
+    let tables: u64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE name LIKE 'vss_test_geo%'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(tables, 0);
+}
+
+/// Pages through points matching `request.filter`, by id or by
+/// `request.order_by`. Ordering by a key creates an index on it first, so
+/// each page is a range scan of that index.
+pub fn scroll(conn: &Connection, name: &str, request: &ScrollRequest) -> Result<ScrollResult> {
+    let config = get_collection_config(conn, name)?;
+    let mut conditions = vec![];
+    let mut values = vec![];
+    if let Some(filter) = compile_filter(name, &config, request.filter.as_ref())? {
+        conditions.push(filter.sql);
+        values.extend(filter.params);
+    }
+    let order = match &request.order_by {
+        None => {
+            if let Some(offset) = request.offset {
+                check_id(offset)?;
+                conditions.push("p.rowid >= ?".to_string());
+                values.push(Value::Integer(offset as i64));
+            }
+            "p.rowid".to_string()
+        }
+        Some(order_by) => {
+            if request.offset.is_some() {
+                return Err(Error::InvalidArgument(
+                    "offset cannot be used with order_by, use order_by.start_from".to_string(),
+                ));
+            }
+            // Uses the index from `create_field_index` if the key has one.
+            let extract = format!(
+                "json_extract(p.payload, {})",
+                filter::json_path(&order_by.key)?
+            );
+            let (op, direction) = match order_by.direction {
+                Direction::Asc => (">=", "ASC"),
+                Direction::Desc => ("<=", "DESC"),
+            };
+            conditions.push(format!("{} IS NOT NULL", extract));
+            if let Some(start_from) = &order_by.start_from {
+                conditions.push(format!("{} {} ?", extract, op));
+                values.push(match start_from {
+                    serde_json::Value::String(s) => Value::Text(s.clone()),
+                    serde_json::Value::Number(n) => match n.as_i64() {
+                        Some(i) => Value::Integer(i),
+                        None => Value::Real(n.as_f64().unwrap_or(f64::NAN)),
+                    },
+                    _ => {
+                        return Err(Error::InvalidArgument(
+                            "order_by.start_from must be a number or a string".to_string(),
+                        ))
+                    }
+                });
+            }
+            format!("{} {}, p.rowid {}", extract, direction, direction)
+        }
+    };
+    // One row more than the page tells whether there is a next page.
+    values.push(sql_limit(request.limit.saturating_add(1)));
+    let condition = match conditions.is_empty() {
+        true => String::new(),
+        false => format!("WHERE {}", conditions.join(" AND ")),
+    };
+    let sql = format!(
+        "SELECT p.rowid FROM vss_{}_payload p {} ORDER BY {} LIMIT ?",
+        name, condition, order
+    );
+    let mut ids = conn
+        .prepare(&sql)?
+        .query_map(params_from_iter(values), |row| row.get::<_, u64>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let next = match ids.len() > request.limit {
+        true => ids.pop(),
+        false => None,
+    };
+
+    let id_str = id_list(&ids)?;
+    let mut vectors = get_vectors(conn, &config, name, &id_str)?;
+    let mut payloads = get_payloads(conn, name, &id_str)?;
+    Ok(ScrollResult {
+        points: ids
+            .into_iter()
+            .map(|id| Point {
+                id,
+                vector: vectors.remove(&id).unwrap_or_default(),
+                payload: payloads.remove(&id).flatten(),
+            })
+            .collect(),
+        next_page_offset: next.filter(|_| request.order_by.is_none()),
+    })
+}
+
+#[test]
+fn test_scroll() {
+    use crate::OrderBy;
+    use serde_json::json;
+
+    let conn = test_conn();
+    create_collections(&conn, "test_scroll", 2).unwrap();
+    let points = (0..10)
+        .map(|i| Point {
+            id: i,
+            vector: vec![i as f32, 0.0].into(),
+            payload: match i {
+                9 => None,
+                _ => json!({"created_at": (i * 7) % 10, "even": i % 2 == 0})
+                    .as_object()
+                    .map(|m| m.to_owned()),
+            },
+        })
+        .collect::<Vec<_>>();
+    add_point(&conn, "test_scroll", &points).unwrap();
+    let ids = |r: &ScrollResult| r.points.iter().map(|p| p.id).collect::<Vec<_>>();
+
+    let mut request = ScrollRequest {
+        limit: 4,
+        ..Default::default()
+    };
+    let r = scroll(&conn, "test_scroll", &request).unwrap();
+    assert_eq!(ids(&r), vec![0, 1, 2, 3]);
+    assert_eq!(r.points[2], points[2]);
+    assert_eq!(r.next_page_offset, Some(4));
+    request.offset = Some(8);
+    let r = scroll(&conn, "test_scroll", &request).unwrap();
+    assert_eq!(ids(&r), vec![8, 9]);
+    assert_eq!(r.next_page_offset, None);
+
+    // created_at of points 0 to 8 is 0, 7, 4, 1, 8, 5, 2, 9, 6, point 9 has
+    // no payload.
+    request.offset = None;
+    request.order_by = Some(OrderBy {
+        key: "created_at".to_string(),
+        direction: Direction::Asc,
+        start_from: Some(json!(2)),
+    });
+    let r = scroll(&conn, "test_scroll", &request).unwrap();
+    assert_eq!(ids(&r), vec![6, 2, 5, 8]);
+    assert_eq!(r.next_page_offset, None);
+
+    request.order_by = Some(OrderBy {
+        key: "created_at".to_string(),
+        direction: Direction::Desc,
+        start_from: None,
+    });
+    request.filter = Some(
+        serde_json::from_value(json!({"must": [{"key": "even", "match": {"value": true}}]}))
+            .unwrap(),
+    );
+    let r = scroll(&conn, "test_scroll", &request).unwrap();
+    assert_eq!(ids(&r), vec![4, 8, 2, 6]);
+
+    // Scrolling doesn't create indexes, but uses those of indexed fields.
+    let plan = |conn: &Connection| {
+        conn.query_row(
+            "EXPLAIN QUERY PLAN SELECT p.rowid FROM vss_test_scroll_payload p ORDER BY json_extract(p.payload, '$.created_at') DESC",
+            [],
+            |row| row.get::<_, String>(3),
+        )
+        .unwrap()
+    };
+    let index = payload_index_name("test_scroll", "created_at");
+    assert!(!plan(&conn).contains(&index));
+    create_field_index(
+        &conn,
+        "test_scroll",
+        "created_at",
+        PayloadSchemaType::Integer,
+    )
+    .unwrap();
+    assert!(plan(&conn).contains(&index));
+    let r = scroll(&conn, "test_scroll", &request).unwrap();
+    assert_eq!(ids(&r), vec![4, 8, 2, 6]);
+}
+
+pub fn search_points(
+    conn: &Connection,
+    name: &str,
+    vector: &[f32],
+    limit: usize,
+) -> Result<Vec<ScoredPoint>> {
+    search(conn, name, &SearchRequest::new(vector.to_vec(), limit))
+}
+
+/// With a filter, the index is asked for this many times more candidates
+/// than requested, since the filter runs on its results. Selective filters
+/// should use exact search.
+const FILTER_CANDIDATE_FACTOR: usize = 10;
+
+pub fn search(conn: &Connection, name: &str, request: &SearchRequest) -> Result<Vec<ScoredPoint>> {
+    let config = get_collection_config(conn, name)?;
+    let filter = compile_filter(name, &config, request.filter.as_ref())?;
+    if let NamedVectorStruct::NamedMulti(query) = &request.vector {
+        if request.mmr.is_some() {
+            return Err(Error::InvalidArgument(
+                "mmr is not supported on multivectors".to_string(),
+            ));
+        }
+        let rows = multivector_search(conn, &config, name, query, request, filter.as_ref())?;
+        return scored_points(conn, &config, name, rows);
+    }
+    let column = query_column(&config, &request.vector)?;
+    let query = request.vector.vector();
+    // MMR picks `request.limit` of a larger pool of nearest candidates.
+    let (limit, diversity) = match &request.mmr {
+        Some(mmr) => {
+            let diversity = mmr.diversity.unwrap_or(DEFAULT_MMR_DIVERSITY);
+            if !(0.0..=1.0).contains(&diversity) {
+                return Err(Error::InvalidArgument(
+                    "mmr diversity must be between 0 and 1".to_string(),
+                ));
+            }
+            let candidates = mmr.candidates_limit.unwrap_or(DEFAULT_MMR_CANDIDATES);
+            (candidates.max(request.limit), Some(diversity))
+        }
+        None => (request.limit, None),
+    };
+
+    if request.limit == 0 {
+        return Ok(vec![]);
+    }
+
+    let (mut rows, rescore) = if request.params.exact {
+        let mut rows = exact_search(
+            conn,
+            &config,
+            name,
+            &column,
+            &[query.to_vec()],
+            limit,
+            filter.as_ref(),
+        )?;
+        (rows.remove(0), false)
+    } else {
+        index_search(
+            conn,
+            &config,
+            name,
+            &column,
+            request,
+            limit,
+            filter.as_ref(),
+        )?
+    };
+
+    let ids = id_list(&rows.iter().map(|(id, _)| *id).collect::<Vec<u64>>())?;
+    let mut vectors = get_vectors(conn, &config, name, &ids)?;
+
+    if rescore {
+        for (id, score) in rows.iter_mut() {
+            if let Some(vector) = vectors.get(id).and_then(|v| v.get(column.name)) {
+                *score = l2_squared(vector, query);
+            }
+        }
+        rows.sort_by(|a, b| a.1.total_cmp(&b.1));
+        rows.truncate(limit);
+    }
+    if let Some(diversity) = diversity {
+        let candidates = rows
+            .into_iter()
+            .filter_map(|(id, score)| {
+                let vector = vectors.get(&id)?.get(column.name)?;
+                Some((id, score, vector))
+            })
+            .collect();
+        rows = mmr(candidates, request.limit, diversity);
+    }
+
+    let mut payloads = get_payloads(conn, name, &ids)?;
+    Ok(rows
+        .into_iter()
+        .map(|(id, score)| ScoredPoint {
+            id,
+            vector: vectors.remove(&id).unwrap_or_default(),
+            payload: payloads.remove(&id).flatten(),
+            score,
+        })
+        .collect())
+}
+
+const DEFAULT_MMR_DIVERSITY: f32 = 0.5;
+const DEFAULT_MMR_CANDIDATES: usize = 100;
+
+/// Greedy maximal marginal relevance over `(id, distance, vector)`
+/// candidates, nearest first. Each step picks the candidate minimizing
+/// `(1 - diversity) * distance - diversity * distance to the nearest pick`,
+/// all squared L2. Picks keep their distance to the query as score.
+fn mmr(candidates: Vec<(u64, f32, &[f32])>, limit: usize, diversity: f32) -> Vec<(u64, f32)> {
+    let mut nearest_pick = vec![f32::INFINITY; candidates.len()];
+    let mut picked = vec![false; candidates.len()];
+    let mut rows = Vec::with_capacity(limit.min(candidates.len()));
+    while rows.len() < limit {
+        let best = candidates
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !picked[*i])
+            .map(|(i, (_, distance, _))| {
+                let redundancy = match rows.is_empty() {
+                    true => 0.0,
+                    false => nearest_pick[i],
+                };
+                (i, (1.0 - diversity) * distance - diversity * redundancy)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        let Some((i, _)) = best else {
+            break;
+        };
+        picked[i] = true;
+        let (id, distance, vector) = candidates[i];
+        rows.push((id, distance));
+        for (j, (_, _, other)) in candidates.iter().enumerate() {
+            nearest_pick[j] = nearest_pick[j].min(l2_squared(vector, other));
+        }
+    }
+    rows
+}
+
+/// Queries vss0, returning `(id, distance)` candidates and whether they
+/// still have to be rescored against the original vectors.
+fn index_search(
+    conn: &Connection,
+    config: &CollectionConfig,
+    name: &str,
+    column: &VectorColumn,
+    request: &SearchRequest,
+    limit: usize,
+    filter: Option<&SqlFilter>,
+) -> Result<(Vec<(u64, f32)>, bool)> {
+    if config.needs_training() {
+        if config.trains_on_first_batch() {
+            // Nothing has been upserted yet.
+            return Ok((vec![], false));
+        }
+        return Err(Error::IndexNotTrained(name.to_string()));
+    }
+
+    let quantization = request.params.quantization.clone().unwrap_or_default();
+    let oversampling = quantization.oversampling.unwrap_or(1.0);
+    if oversampling.is_nan() || oversampling < 1.0 {
+        return Err(Error::InvalidArgument(
+            "oversampling must be at least 1.0".to_string(),
+        ));
+    }
+    let rescore = config.stores_original_vectors() && quantization.rescore.unwrap_or(true);
+    let candidates = if rescore {
+        (limit as f64 * oversampling as f64).ceil() as usize
+    } else {
+        limit
+    };
+
+    let vector_raw = vector_to_blob(request.vector.vector());
+    let mut values: Vec<Value> = vec![Value::Blob(vector_raw)];
+    let sql = match filter {
+        None => {
+            values.push(sql_limit(candidates));
+            format!(
+                r#"
+                SELECT rowid,distance FROM {} WHERE vss_search({},vector_from_raw(?)) ORDER BY distance LIMIT ?;
+                "#,
+                index_table(name, config),
+                column.index_column()
+            )
+        }
+        Some(filter) => {
+            values.push(sql_limit(
+                candidates.saturating_mul(FILTER_CANDIDATE_FACTOR),
+            ));
+            values.extend(filter.params.iter().cloned());
+            values.push(sql_limit(candidates));
+            format!(
+                r#"
+                SELECT v.rowid,v.distance FROM (
+                    SELECT rowid,distance FROM {} WHERE vss_search({},vector_from_raw(?)) LIMIT ?
+                ) v JOIN vss_{}_payload p ON p.rowid = v.rowid
+                WHERE {} ORDER BY v.distance LIMIT ?;
+                "#,
+                index_table(name, config),
+                column.index_column(),
+                name,
+                filter.sql
+            )
+        }
+    };
+
+    let mut stmt = conn.prepare(sql.as_str())?;
+    let rows = stmt
+        .query_map(params_from_iter(values), |row| {
+            let id: u64 = row.get(0)?;
+            let score: f32 = row.get(1)?;
+            Ok((id, score))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok((rows, rescore))
+}
+
+fn sql_limit(limit: usize) -> Value {
+    Value::Integer(i64::try_from(limit).unwrap_or(i64::MAX))
+}
+
+/// Same metric as the `distance` column of a FAISS L2 index.
+fn l2_squared(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+/// `ids` is a comma separated list as built by [`id_list`].
+fn get_vectors(
+    conn: &Connection,
+    config: &CollectionConfig,
+    name: &str,
+    ids: &str,
+) -> Result<HashMap<u64, VectorStruct>> {
+    let sql = format!(
+        r#"
+        {} WHERE rowid in ({});
+        "#,
+        vector_source(config, name),
+        ids
+    );
+    let columns = vector_columns(config);
+    let mut stmt = conn.prepare(sql.as_str())?;
+    let mut rows = stmt.query([])?;
+
+    let mut map = HashMap::new();
+    while let Some(row) = rows.next()? {
+        map.insert(row.get::<_, u64>(0)?, row_vectors(row, &columns)?);
+    }
+
+    if !config.sparse_vectors.is_empty() {
+        let sql = format!(
+            r#"
+            SELECT point_id,vector,dim,value FROM vss_{}_sparse WHERE point_id in ({}) ORDER BY point_id,vector,dim;
+            "#,
+            name, ids
+        );
+        let mut stmt = conn.prepare(sql.as_str())?;
+        let mut rows = stmt.query([])?;
+        let mut sparse = BTreeMap::<(u64, String), SparseVector>::new();
+        while let Some(row) = rows.next()? {
+            let vector = sparse.entry((row.get(0)?, row.get(1)?)).or_default();
+            vector.indices.push(row.get(2)?);
+            vector.values.push(row.get(3)?);
+        }
+        for ((id, vector_name), vector) in sparse {
+            if let Some(vectors) = map.get_mut(&id) {
+                vectors.insert(vector_name, vector.into());
+            }
+        }
+    }
+    for vector_name in config.multivectors.keys() {
+        let (_, tokens) = multivector_tables(name, vector_name);
+        let sql = format!(
+            "SELECT point_id,vector FROM {} WHERE point_id in ({}) ORDER BY point_id,rowid",
+            tokens, ids
+        );
+        let mut stmt = conn.prepare(sql.as_str())?;
+        let mut rows = stmt.query([])?;
+        let mut multi = BTreeMap::<u64, Vec<Vec<f32>>>::new();
+        while let Some(row) = rows.next()? {
+            let token = blob_to_vector(&row.get::<_, Vec<u8>>(1)?)?;
+            multi.entry(row.get(0)?).or_default().push(token);
+        }
+        for (id, tokens) in multi {
+            if let Some(vectors) = map.get_mut(&id) {
+                vectors.insert(vector_name.clone(), tokens.into());
+            }
+        }
+    }
+    Ok(map)
+}
+
+#[test]
+fn test_points_search() {
+    use serde_json::json;
+    let conn = test_conn();
+    create_collections(&conn, "test_vss", 4).unwrap();
+    let mut points = Vec::<Point>::new();
+    {
+        points.push(Point {
+            id: 1,
+            vector: vec![0.05, 0.61, 0.76, 0.74].into(),
+            payload: json!({"city": "Berlin"}).as_object().map(|m| m.to_owned()),
+        });
+        points.push(Point {
+            id: 2,
+            vector: vec![0.19, 0.81, 0.75, 0.11].into(),
+            payload: json!({"city": "London"}).as_object().map(|m| m.to_owned()),
+        });
+        points.push(Point {
+            id: 3,
+            vector: vec![0.36, 0.55, 0.47, 0.94].into(),
+            payload: json!({"city": "Moscow"}).as_object().map(|m| m.to_owned()),
+        });
+        points.push(Point {
+            id: 4,
+            vector: vec![0.18, 0.01, 0.85, 0.80].into(),
+            payload: json!({"city": "New York"})
+                .as_object()
+                .map(|m| m.to_owned()),
+        });
+        points.push(Point {
+            id: 5,
+            vector: vec![0.24, 0.18, 0.22, 0.44].into(),
+            payload: json!({"city": "Beijing"}).as_object().map(|m| m.to_owned()),
+        });
+        points.push(Point {
+            id: 6,
+            vector: vec![0.35, 0.08, 0.11, 0.44].into(),
+            payload: json!({"city": "Mumbai"}).as_object().map(|m| m.to_owned()),
+        });
+    }
+    let r = add_point(&conn, "test_vss", &points).unwrap();
+    assert_eq!(r, vec![1, 2, 3, 4, 5, 6]);
+
+    let q = vec![0.2, 0.1, 0.9, 0.7];
+    let r = search_points(&conn, "test_vss", &q, 2).unwrap();
+    assert_eq!(r.len(), 2);
+    assert_eq!(r[0].id, 4);
+    assert_eq!(r[1].id, 1);
+}
+
+#[test]
+fn test_points_search_quantized() {
+    use crate::{QuantizationSearchParams, ScalarQuantization, ScalarType, SearchParams};
+
+    let conn = test_conn();
+    let mut config = CollectionConfig::new(4);
+    config.quantization_config = Some(QuantizationConfig::Scalar(ScalarQuantization {
+        kind: ScalarType::Int8,
+    }));
+    create_collection_with_config(&conn, "test_sq8", &config).unwrap();
+
+    let points = (0..64)
+        .map(|i| Point {
+            id: i,
+            vector: vec![(i % 8) as f32 / 8.0, (i / 8) as f32 / 8.0, 0.5, 0.25].into(),
+            payload: None,
+        })
+        .collect::<Vec<_>>();
+    add_point(&conn, "test_sq8", &points).unwrap();
+    assert!(
+        get_collection_config(&conn, "test_sq8")
+            .unwrap()
+            .index_trained
+    );
+
+    // Originals come back exactly, not as int8 reconstructions.
+    let r = get_point(&conn, "test_sq8", 9).unwrap().unwrap();
+    assert_eq!(r.vector, points[9].vector);
+
+    let request = SearchRequest {
+        vector: points[9].vector.get(None).unwrap().to_vec().into(),
+        limit: 3,
+        filter: None,
+        params: SearchParams {
+            quantization: Some(QuantizationSearchParams {
+                rescore: Some(true),
+                oversampling: Some(4.0),
+            }),
+            ..Default::default()
+        },
+        mmr: None,
+    };
+    let r = search(&conn, "test_sq8", &request).unwrap();
+    assert_eq!(r.len(), 3);
+    assert_eq!(r[0].id, 9);
+    assert_eq!(r[0].score, 0.0);
+    assert!(r.windows(2).all(|w| w[0].score <= w[1].score));
+
+    let info = get_collections_info(&conn, "test_sq8").unwrap();
+    let quantization = info.quantization.unwrap();
+    assert_eq!(quantization.compression_ratio, 4.0);
+    assert_eq!(quantization.index_bytes, 64 * 4);
+    assert_eq!(quantization.original_vectors_bytes, 64 * 16);
+
+    delete_points(&conn, "test_sq8", vec![9]).unwrap();
+    assert!(get_point(&conn, "test_sq8", 9).unwrap().is_none());
+    let quantization = get_collections_info(&conn, "test_sq8")
+        .unwrap()
+        .quantization
+        .unwrap();
+    assert_eq!(quantization.index_bytes, 63 * 4);
+    assert_eq!(quantization.original_vectors_bytes, 63 * 16);
+}
+
+#[test]
+fn test_points_search_exact_filtered() {
+    use serde_json::json;
+    let conn = test_conn();
+    create_collections(&conn, "test_vss", 2).unwrap();
+    let points = (0..100)
+        .map(|i| Point {
+            id: i,
+            vector: vec![i as f32, 0.0].into(),
+            payload: json!({"even": i % 2 == 0, "rank": i})
+                .as_object()
+                .map(|m| m.to_owned()),
+        })
+        .collect::<Vec<_>>();
+    add_point(&conn, "test_vss", &points).unwrap();
+
+    let mut request = SearchRequest::new(vec![10.2, 0.0], 3);
+    request.params.exact = true;
+    let r = search(&conn, "test_vss", &request).unwrap();
+    assert_eq!(r.iter().map(|p| p.id).collect::<Vec<_>>(), vec![10, 11, 9]);
+    assert_eq!(r[0].vector, points[10].vector);
+
+    // Only one point matches, far from the query: the index would have to
+    // return every point for a post-filter to find it, exact search does not.
+    request.filter = Some(
+        serde_json::from_value(json!({
+            "must": [{"key": "even", "match": {"value": false}}],
+            "must_not": [{"key": "rank", "range": {"lt": 97}}, {"has_id": [99]}]
+        }))
+        .unwrap(),
+    );
+    let r = search(&conn, "test_vss", &request).unwrap();
+    assert_eq!(r.len(), 1);
+    assert_eq!(r[0].id, 97);
+    assert_eq!(r[0].payload, points[97].payload);
+
+    request.filter = Some(
+        serde_json::from_value(json!({"must": [{"key": "rank", "range": {"gte": 50}}]})).unwrap(),
+    );
+    request.params.exact = false;
+    let r = search(&conn, "test_vss", &request).unwrap();
+    assert!(r.iter().all(|p| p.id >= 50));
+}
+
+#[test]
+fn test_mmr_search() {
+    use crate::Mmr;
+
+    let conn = test_conn();
+    create_collections(&conn, "test_mmr", 2).unwrap();
+    let points = [
+        vec![0.0, 0.0],
+        vec![0.01, 0.0],
+        vec![0.02, 0.0],
+        vec![-0.1, 0.5],
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(i, vector)| Point {
+        id: i as u64,
+        vector: vector.into(),
+        payload: None,
+    })
+    .collect::<Vec<_>>();
+    add_point(&conn, "test_mmr", &points).unwrap();
+
+    let mut request = SearchRequest::new(vec![-0.1, 0.0], 2);
+    request.mmr = Some(Mmr {
+        diversity: Some(0.0),
+        candidates_limit: Some(4),
+    });
+    let r = search(&conn, "test_mmr", &request).unwrap();
+    assert_eq!(r.iter().map(|p| p.id).collect::<Vec<_>>(), vec![0, 1]);
+
+    // Point 1 is nearly a copy of point 0, point 3 is further from the
+    // query but far from point 0.
+    request.mmr = Some(Mmr::default());
+    let r = search(&conn, "test_mmr", &request).unwrap();
+    assert_eq!(r.iter().map(|p| p.id).collect::<Vec<_>>(), vec![0, 3]);
+    assert_eq!(r[1].score, l2_squared(&[-0.1, 0.5], &[-0.1, 0.0]));
+
+    request.mmr = Some(Mmr {
+        diversity: Some(1.5),
+        candidates_limit: None,
+    });
+    assert!(matches!(
+        search(&conn, "test_mmr", &request),
+        Err(Error::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_named_vectors() {
+    use crate::{NamedVector, NamedVectorStruct, VectorParams, VectorsConfig};
+
+    let conn = test_conn();
+    let vectors = VectorsConfig::Multi(BTreeMap::from([
+        ("text".to_string(), VectorParams { size: 4 }),
+        ("image".to_string(), VectorParams { size: 2 }),
+    ]));
+    create_collection_with_config(
+        &conn,
+        "test_named",
+        &CollectionConfig::with_vectors(vectors),
+    )
+    .unwrap();
+
+    let points = (0..8)
+        .map(|i| Point {
+            id: i,
+            vector: VectorStruct::Named(BTreeMap::from([
+                ("text".to_string(), vec![i as f32, 0.0, 0.0, 0.0].into()),
+                ("image".to_string(), vec![0.0, (8 - i) as f32].into()),
+            ])),
+            payload: None,
+        })
+        .collect::<Vec<_>>();
+    add_point(&conn, "test_named", &points).unwrap();
+    assert_eq!(
+        get_point(&conn, "test_named", 2).unwrap().unwrap(),
+        points[2]
+    );
+
+    let search_named = |name: &str, vector: Vec<f32>| {
+        let mut request = SearchRequest::new(vec![], 1);
+        request.vector = NamedVectorStruct::Named(NamedVector {
+            name: name.to_string(),
+            vector,
+        });
+        search(&conn, "test_named", &request)
+    };
+    let r = search_named("text", vec![6.1, 0.0, 0.0, 0.0]).unwrap();
+    assert_eq!(r[0].id, 6);
+    assert_eq!(r[0].vector, points[6].vector);
+    let r = search_named("image", vec![0.0, 6.9]).unwrap();
+    assert_eq!(r[0].id, 1);
+
+    assert!(matches!(
+        search_named("image", vec![0.0; 4]),
+        Err(Error::DimensionMismatch {
+            expected: 2,
+            got: 4
+        })
+    ));
+    assert!(matches!(
+        search_named("audio", vec![0.0; 2]),
+        Err(Error::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        search_points(&conn, "test_named", &[0.0; 4], 1),
+        Err(Error::InvalidArgument(_))
+    ));
+    let missing = Point {
+        id: 9,
+        vector: VectorStruct::Named(BTreeMap::from([("text".to_string(), vec![0.0; 4].into())])),
+        payload: None,
+    };
+    assert!(matches!(
+        add_point(&conn, "test_named", &[missing]),
+        Err(Error::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn test_multivector_search() {
+    use crate::{MultiVectorParams, NamedMultiVector, VectorsConfig};
+    use serde_json::json;
+
+    let conn = test_conn();
+    let mut config = CollectionConfig::with_vectors(VectorsConfig::Multi(BTreeMap::new()));
+    config.multivectors.insert(
+        "colbert".to_string(),
+        MultiVectorParams {
+            size: 2,
+            comparator: Default::default(),
+        },
+    );
+    create_collection_with_config(&conn, "test_mv", &config).unwrap();
+
+    let point = |id: u64, tokens: Vec<Vec<f32>>| Point {
+        id,
+        vector: VectorStruct::Named(BTreeMap::from([("colbert".to_string(), tokens.into())])),
+        payload: json!({"id": id}).as_object().map(|m| m.to_owned()),
+    };
+    let points = vec![
+        point(1, vec![vec![1.0, 0.0], vec![0.0, 1.0]]),
+        point(2, vec![vec![1.0, 0.0], vec![1.0, 0.0], vec![0.9, 0.1]]),
+        point(3, vec![vec![0.0, 1.5]]),
+    ];
+    add_point(&conn, "test_mv", &points).unwrap();
+    assert_eq!(get_point(&conn, "test_mv", 2).unwrap().unwrap(), points[1]);
+
+    let mut request = SearchRequest::new(vec![], 3);
+    request.vector = NamedVectorStruct::NamedMulti(NamedMultiVector {
+        name: "colbert".to_string(),
+        vector: vec![vec![1.0, 0.0], vec![0.0, 1.0]],
+    });
+    // Point 1 matches both query tokens, point 3 only the second one, and
+    // the three tokens of point 2 all match the first one.
+    let r = search(&conn, "test_mv", &request).unwrap();
+    assert_eq!(r.iter().map(|p| p.id).collect::<Vec<_>>(), vec![1, 3, 2]);
+    assert_eq!(r[0].score, 2.0);
+    assert_eq!(r[1].score, 1.5);
+    assert_eq!(r[2].score, 1.1);
+    assert_eq!(r[0].vector, points[0].vector);
+
+    request.params.exact = true;
+    request.filter = Some(serde_json::from_value(json!({"must_not": [{"has_id": [1]}]})).unwrap());
+    let r = search(&conn, "test_mv", &request).unwrap();
+    assert_eq!(r.iter().map(|p| p.id).collect::<Vec<_>>(), vec![3, 2]);
+
+    // Upserts replace the tokens, deletes remove them.
+    add_point(&conn, "test_mv", &[point(3, vec![vec![0.0, 0.1]])]).unwrap();
+    delete_points(&conn, "test_mv", vec![1]).unwrap();
+    request.params.exact = false;
+    request.filter = None;
+    let r = search(&conn, "test_mv", &request).unwrap();
+    assert_eq!(r.iter().map(|p| p.id).collect::<Vec<_>>(), vec![2, 3]);
+
+    request.vector = NamedVectorStruct::NamedMulti(NamedMultiVector {
+        name: "colbert".to_string(),
+        vector: vec![vec![1.0, 0.0, 0.0]],
+    });
+    assert!(matches!(
+        search(&conn, "test_mv", &request),
+        Err(Error::DimensionMismatch {
+            expected: 2,
+            got: 3
+        })
+    ));
+    let dense = Point {
+        vector: VectorStruct::Named(BTreeMap::from([(
+            "colbert".to_string(),
+            vec![1.0, 0.0].into(),
+        )])),
+        ..point(4, vec![])
+    };
+    assert!(matches!(
+        add_point(&conn, "test_mv", &[dense]),
+        Err(Error::InvalidArgument(_))
+    ));
+    delete_collection(&conn, "test_mv").unwrap();
+}
+
+/// Upper bound on the points a group search looks at before returning the
+/// groups it has, filled or not.
+const MAX_GROUP_CANDIDATES: usize = 10_000;
+
+/// Searches with a growing limit until the first `limit` groups have
+/// `group_size` hits each, or there are no more points. Groups are ordered
+/// by their best hit. Points with an array value join a group per element.
+pub fn search_groups(
+    conn: &Connection,
+    name: &str,
+    request: &SearchGroupsRequest,
+) -> Result<Vec<PointGroup>> {
+    if request.group_by.is_empty() || request.group_by.split('.').any(str::is_empty) {
+        return Err(Error::InvalidArgument(format!(
+            "`{}` is not a valid payload key",
+            request.group_by
+        )));
+    }
+    if request.group_size == 0 {
+        return Err(Error::InvalidArgument(
+            "group_size must be greater than 0".to_string(),
+        ));
+    }
+    if request.limit == 0 {
+        return Ok(vec![]);
+    }
+
+    let mut search_request = SearchRequest {
+        vector: request.vector.clone(),
+        limit: request
+            .limit
+            .saturating_mul(request.group_size)
+            .min(MAX_GROUP_CANDIDATES),
+        filter: request.filter.clone(),
+        params: request.params.clone(),
+        mmr: None,
+    };
+    let mut previous = 0;
+    loop {
+        let points = search(conn, name, &search_request)?;
+        let found = points.len();
+        let mut groups: Vec<PointGroup> = vec![];
+        let mut index = HashMap::new();
+        for point in points {
+            for value in group_values(point.payload.as_ref(), &request.group_by) {
+                let i = *index.entry(value.to_string()).or_insert_with(|| {
+                    groups.push(PointGroup {
+                        id: value,
+                        hits: vec![],
+                    });
+                    groups.len() - 1
+                });
+                if groups[i].hits.len() < request.group_size {
+                    groups[i].hits.push(point.clone());
+                }
+            }
+        }
+        groups.truncate(request.limit);
+
+        let filled = groups.len() == request.limit
+            && groups.iter().all(|g| g.hits.len() == request.group_size);
+        // A filtered index search can return fewer points than asked for
+        // while more exist, so only a search that found nothing new ends it.
+        let exhausted =
+            found <= previous || (found < search_request.limit && request.filter.is_none());
+        if filled || exhausted || search_request.limit == MAX_GROUP_CANDIDATES {
+            return Ok(groups);
+        }
+        previous = found;
+        search_request.limit = search_request
+            .limit
+            .saturating_mul(2)
+            .min(MAX_GROUP_CANDIDATES);
+    }
+}
+
+/// The value at `key`, a dotted path into the payload.
+fn payload_value<'a>(payload: Option<&'a Payload>, key: &str) -> Option<&'a serde_json::Value> {
+    let mut parts = key.split('.');
+    let mut value = payload?.get(parts.next()?);
+    for part in parts {
+        value = value?.get(part);
+    }
+    value
+}
+
+/// Strings and integers at `key`, a dotted path into the payload.
+fn group_values(payload: Option<&Payload>, key: &str) -> Vec<serde_json::Value> {
+    let values = match payload_value(payload, key) {
+        Some(serde_json::Value::Array(values)) => values.clone(),
+        Some(value) => vec![value.clone()],
+        None => vec![],
+    };
+    values
+        .into_iter()
+        .filter(|v| v.is_string() || v.is_i64() || v.is_u64())
+        .collect()
+}
+
+#[test]
+fn test_search_groups() {
+    use serde_json::json;
+
+    let conn = test_conn();
+    create_collections(&conn, "test_groups", 2).unwrap();
+    // Ten chunks of document 0 are nearest to the query, then two chunks
+    // each of documents 1 and 2.
+    let chunk = |id: u64, x: f32, doc: serde_json::Value| Point {
+        id,
+        vector: vec![x, 0.0].into(),
+        payload: json!({"doc": doc}).as_object().map(|m| m.to_owned()),
+    };
+    let mut points = (0..10)
+        .map(|i| chunk(i, i as f32 * 0.01, json!(0)))
+        .collect::<Vec<_>>();
+    points.push(chunk(10, 1.0, json!(1)));
+    points.push(chunk(11, 1.1, json!("two")));
+    points.push(chunk(12, 1.2, json!(1)));
+    points.push(chunk(13, 1.3, json!("two")));
+    points.push(chunk(14, 1.4, json!([1, "two"])));
+    add_point(&conn, "test_groups", &points).unwrap();
+
+    let mut request = SearchGroupsRequest {
+        vector: vec![0.0, 0.0].into(),
+        group_by: "doc".to_string(),
+        group_size: 2,
+        limit: 3,
+        filter: None,
+        params: Default::default(),
+    };
+    let groups = search_groups(&conn, "test_groups", &request).unwrap();
+    assert_eq!(
+        groups.iter().map(|g| g.id.clone()).collect::<Vec<_>>(),
+        vec![json!(0), json!(1), json!("two")]
+    );
+    let hits = |group: &PointGroup| group.hits.iter().map(|p| p.id).collect::<Vec<_>>();
+    assert_eq!(hits(&groups[0]), vec![0, 1]);
+    assert_eq!(hits(&groups[1]), vec![10, 12]);
+    assert_eq!(hits(&groups[2]), vec![11, 13]);
+
+    // Fewer groups than asked for: every point is looked at once.
+    request.limit = 5;
+    request.group_size = 3;
+    let groups = search_groups(&conn, "test_groups", &request).unwrap();
+    assert_eq!(groups.len(), 3);
+    assert_eq!(hits(&groups[1]), vec![10, 12, 14]);
+    assert_eq!(hits(&groups[2]), vec![11, 13, 14]);
+}
+
+/// Rank offset of reciprocal rank fusion, the usual value from the paper.
+const RRF_K: f32 = 60.0;
+
+/// Dot-product search over the inverted index of a sparse vector. Scores
+/// are similarities, so higher is better.
+pub fn search_sparse(
+    conn: &Connection,
+    name: &str,
+    request: &SparseSearchRequest,
+) -> Result<Vec<ScoredPoint>> {
+    let config = get_collection_config(conn, name)?;
+    if !config.sparse_vectors.contains_key(&request.vector.name) {
+        return Err(unknown_vector(&request.vector.name));
+    }
+    check_sparse(&request.vector.vector)?;
+    let filter = compile_filter(name, &config, request.filter.as_ref())?;
+    if request.limit == 0 || request.vector.vector.indices.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let query = request
+        .vector
+        .vector
+        .indices
+        .iter()
+        .zip(&request.vector.vector.values)
+        .collect::<Vec<_>>();
+    let mut values = vec![
+        Value::Text(serde_json::to_string(&query)?),
+        Value::Text(request.vector.name.clone()),
+    ];
+    let (join, condition) = match &filter {
+        Some(filter) => {
+            values.extend(filter.params.iter().cloned());
+            (
+                format!("JOIN vss_{}_payload p ON p.rowid = s.point_id", name),
+                format!("WHERE {}", filter.sql),
+            )
+        }
+        None => (String::new(), String::new()),
+    };
+    values.push(sql_limit(request.limit));
+    let sql = format!(
+        r#"
+        WITH q(dim, value) AS (
+            SELECT json_extract(value, '$[0]'), json_extract(value, '$[1]') FROM json_each(?)
+        )
+        SELECT s.point_id, SUM(s.value * q.value) AS score
+        FROM q JOIN vss_{}_sparse s ON s.vector = ? AND s.dim = q.dim {}
+        {}
+        GROUP BY s.point_id ORDER BY score DESC, s.point_id LIMIT ?;
+        "#,
+        name, join, condition
+    );
+    let mut stmt = conn.prepare(sql.as_str())?;
+    let rows = stmt
+        .query_map(params_from_iter(values), |row| {
+            Ok((row.get::<_, u64>(0)?, row.get::<_, f64>(1)? as f32))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    scored_points(conn, &config, name, rows)
+}
+
+/// Loads vectors and payloads of ranked `(id, score)` rows.
+fn scored_points(
+    conn: &Connection,
+    config: &CollectionConfig,
+    name: &str,
+    rows: Vec<(u64, f32)>,
+) -> Result<Vec<ScoredPoint>> {
+    let ids = id_list(&rows.iter().map(|(id, _)| *id).collect::<Vec<u64>>())?;
+    let mut vectors = get_vectors(conn, config, name, &ids)?;
+    let mut payloads = get_payloads(conn, name, &ids)?;
+    Ok(rows
+        .into_iter()
+        .map(|(id, score)| ScoredPoint {
+            id,
+            vector: vectors.remove(&id).unwrap_or_default(),
+            payload: payloads.remove(&id).flatten(),
+            score,
+        })
+        .collect())
+}
+
+/// Tokens are looked up this many times more often than points requested,
+/// since the nearest tokens of a query token tend to come from few points.
+const TOKEN_CANDIDATE_FACTOR: usize = 8;
+
+/// Late interaction search: every query token retrieves its nearest stored
+/// tokens, and the points they belong to are rescored with MaxSim over all
+/// of their tokens. Exact search rescores every point. Scores are
+/// similarities, so higher is better.
+fn multivector_search(
+    conn: &Connection,
+    config: &CollectionConfig,
+    name: &str,
+    query: &NamedMultiVector,
+    request: &SearchRequest,
+    filter: Option<&SqlFilter>,
+) -> Result<Vec<(u64, f32)>> {
+    let Some(params) = config.multivectors.get(&query.name) else {
+        return Err(unknown_vector(&query.name));
+    };
+    check_tokens(params.size, &query.vector)?;
+    if request.limit == 0 || query.vector.is_empty() {
+        return Ok(vec![]);
+    }
+    let (index, tokens) = multivector_tables(name, &query.name);
+
+    let candidates = if request.params.exact {
+        None
+    } else {
+        let mut per_token = request.limit.saturating_mul(TOKEN_CANDIDATE_FACTOR);
+        if filter.is_some() {
+            per_token = per_token.saturating_mul(FILTER_CANDIDATE_FACTOR);
+        }
+        let mut stmt = conn.prepare(&format!(
+            r#"
+            SELECT t.point_id FROM (
+                SELECT rowid FROM {} WHERE vss_search(token,vector_from_raw(?1)) LIMIT ?2
+            ) v JOIN {} t ON t.rowid = v.rowid;
+            "#,
+            index, tokens
+        ))?;
+        let mut ids = BTreeSet::new();
+        for token in &query.vector {
+            let rows = stmt.query_map(
+                params![vector_to_blob(token), sql_limit(per_token)],
+                |row| row.get::<_, u64>(0),
+            )?;
+            for id in rows {
+                ids.insert(id?);
+            }
+        }
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        Some(id_list(&ids.into_iter().collect::<Vec<_>>())?)
+    };
+
+    let mut conditions = vec![];
+    let mut values = vec![];
+    if let Some(ids) = &candidates {
+        conditions.push(format!("t.point_id in ({})", ids));
+    }
+    let join = match filter {
+        Some(filter) => {
+            conditions.push(filter.sql.clone());
+            values.extend(filter.params.iter().cloned());
+            format!("JOIN vss_{}_payload p ON p.rowid = t.point_id", name)
+        }
+        None => String::new(),
+    };
+    let condition = match conditions.is_empty() {
+        true => String::new(),
+        false => format!("WHERE {}", conditions.join(" AND ")),
+    };
+    let sql = format!(
+        "SELECT t.point_id,t.vector FROM {} t {} {} ORDER BY t.point_id",
+        tokens, join, condition
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(params_from_iter(values))?;
+
+    // Negated scores, so the max-heap keeps the `limit` most similar.
+    let mut heap = BinaryHeap::with_capacity(request.limit + 1);
+    let mut push = |id: u64, best: &[f32]| {
+        let score = best.iter().sum::<f32>();
+        heap.push(Neighbor(-score, id));
+        if heap.len() > request.limit {
+            heap.pop();
+        }
+    };
+    let mut current = None;
+    let mut best = vec![f32::NEG_INFINITY; query.vector.len()];
+    while let Some(row) = rows.next()? {
+        let id: u64 = row.get(0)?;
+        if let Some(previous) = current.filter(|&current| current != id) {
+            push(previous, &best);
+            best.fill(f32::NEG_INFINITY);
+        }
+        current = Some(id);
+        let token = blob_to_vector(&row.get::<_, Vec<u8>>(1)?)?;
+        for (query, best) in query.vector.iter().zip(best.iter_mut()) {
+            *best = best.max(dot(query, &token));
+        }
+    }
+    if let Some(id) = current {
+        push(id, &best);
+    }
+    Ok(heap
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Neighbor(score, id)| (id, -score))
+        .collect())
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Runs every search of `request` and merges the rankings. Dense and sparse
+/// scores are not comparable, so only ranks are used; the fused score is
+/// higher for better matches.
+pub fn fuse(conn: &Connection, name: &str, request: &FusionRequest) -> Result<Vec<ScoredPoint>> {
+    if request.dense.is_empty() && request.sparse.is_empty() {
+        return Err(Error::InvalidArgument(
+            "fusion needs at least one dense or sparse search".to_string(),
+        ));
+    }
+    let mut rankings = vec![];
+    for dense in &request.dense {
+        rankings.push(search(conn, name, dense)?);
+    }
+    for sparse in &request.sparse {
+        rankings.push(search_sparse(conn, name, sparse)?);
+    }
+
+    let mut fused = HashMap::<u64, ScoredPoint>::new();
+    for ranking in rankings {
+        for (rank, point) in ranking.into_iter().enumerate() {
+            let score = match request.fusion {
+                Fusion::Rrf => 1.0 / (RRF_K + rank as f32 + 1.0),
+            };
+            fused
+                .entry(point.id)
+                .or_insert(ScoredPoint {
+                    score: 0.0,
+                    ..point
+                })
+                .score += score;
+        }
+    }
+    let mut points = fused.into_values().collect::<Vec<_>>();
+    points.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.id.cmp(&b.id)));
+    points.truncate(request.limit);
+    Ok(points)
+}
+
+#[test]
+fn test_sparse_search_and_fusion() {
+    use crate::{NamedSparseVector, SparseVectorParams};
+
+    let conn = test_conn();
+    let mut config = CollectionConfig::new(2);
+    config
+        .sparse_vectors
+        .insert("bm25".to_string(), SparseVectorParams::default());
+    create_collection_with_config(&conn, "test_sparse", &config).unwrap();
+
+    let sparse = |indices: Vec<u32>, values: Vec<f32>| SparseVector { indices, values };
+    let point = |id: u64, dense: Vec<f32>, bm25: Option<SparseVector>| {
+        let mut vector = VectorStruct::Single(dense);
+        if let Some(bm25) = bm25 {
+            vector.insert("bm25".to_string(), bm25.into());
+        }
+        Point {
+            id,
+            vector,
+            payload: None,
+        }
+    };
+    let points = vec![
+        point(1, vec![0.0, 0.0], Some(sparse(vec![1, 7], vec![1.0, 2.0]))),
+        point(2, vec![1.0, 0.0], Some(sparse(vec![7, 9], vec![0.5, 3.0]))),
+        point(3, vec![5.0, 5.0], None),
+    ];
+    add_point(&conn, "test_sparse", &points).unwrap();
+    assert_eq!(
+        get_point(&conn, "test_sparse", 2).unwrap().unwrap(),
+        points[1]
+    );
+    assert_eq!(
+        get_point(&conn, "test_sparse", 3).unwrap().unwrap(),
+        points[2]
+    );
+
+    let request = SparseSearchRequest {
+        vector: NamedSparseVector {
+            name: "bm25".to_string(),
+            vector: sparse(vec![7, 9], vec![1.0, 1.0]),
+        },
+        limit: 10,
+        filter: None,
+    };
+    let r = search_sparse(&conn, "test_sparse", &request).unwrap();
+    assert_eq!(r.iter().map(|p| p.id).collect::<Vec<_>>(), vec![2, 1]);
+    assert_eq!(r[0].score, 3.5);
+    assert_eq!(r[1].score, 2.0);
+
+    let bad = Point {
+        id: 4,
+        ..point(4, vec![0.0, 0.0], Some(sparse(vec![1, 1], vec![1.0, 2.0])))
+    };
+    assert!(matches!(
+        add_point(&conn, "test_sparse", &[bad]),
+        Err(Error::InvalidArgument(_))
+    ));
+
+    // Point 2 leads both rankings and point 1 is second in both, point 3
+    // only shows up in the dense one.
+    let request = FusionRequest {
+        dense: vec![SearchRequest::new(vec![0.9, 0.0], 3)],
+        sparse: vec![request],
+        limit: 2,
+        fusion: Fusion::Rrf,
+    };
+    let r = fuse(&conn, "test_sparse", &request).unwrap();
+    assert_eq!(r.len(), 2);
+    assert_eq!(r[0].id, 2);
+    assert_eq!(r[0].score, 2.0 / 61.0);
+    assert_eq!(r[1].id, 1);
+
+    delete_points(&conn, "test_sparse", vec![2]).unwrap();
+    let request = SparseSearchRequest {
+        vector: NamedSparseVector {
+            name: "bm25".to_string(),
+            vector: sparse(vec![9], vec![1.0]),
+        },
+        limit: 10,
+        filter: None,
+    };
+    assert!(search_sparse(&conn, "test_sparse", &request)
+        .unwrap()
+        .is_empty());
+}
+
+/// Vectors sampled for training when the request does not say otherwise.
+const DEFAULT_TRAINING_SAMPLE: usize = 100_000;
+
+/// Trains the index on a random sample of the stored vectors, then adds all
+/// of them to vss0. Runs in one transaction, so searches keep failing with
+/// [`Error::IndexNotTrained`] until the index is complete.
+pub fn train_index(
+    conn: &Connection,
+    name: &str,
+    sample_size: Option<usize>,
+) -> Result<TrainReport> {
+    let mut config = get_collection_config(conn, name)?;
+    let sample_size = check_trainable(name, &config, sample_size)?;
+
+    let columns = vector_columns(&config);
+    let table = index_table(name, &config);
+    let vectors = vector_source(&config, name);
+    let tx = conn.unchecked_transaction()?;
+    let trained_on = train_from(&tx, &columns, &table, &vectors, sample_size)?;
+    if trained_on == 0 {
+        return Err(Error::InvalidArgument(format!(
+            "collection `{}` has no points to train on",
+            name
+        )));
+    }
+    let points_indexed = index_from(&tx, &columns, &table, &vectors)?;
+    config.index_trained = true;
+    save_collection_config(&tx, name, &config)?;
+    tx.commit()?;
+
+    Ok(TrainReport {
+        index_factory: config.index_factory(),
+        trained_on,
+        points_indexed,
+    })
+}
+
+/// Checks that the index of `name` can be trained, returning the sample
+/// size to train on.
+fn check_trainable(
+    name: &str,
+    config: &CollectionConfig,
+    sample_size: Option<usize>,
+) -> Result<usize> {
+    if !config.stores_original_vectors() {
+        return Err(Error::InvalidArgument(format!(
+            "index `{}` does not need training",
+            config.index_factory()
+        )));
+    }
+    if config.index_trained {
+        return Err(Error::InvalidArgument(format!(
+            "index of collection `{}` is already trained",
+            name
+        )));
+    }
+    if config.rebuild.is_some() {
+        return Err(Error::InvalidArgument(format!(
+            "index of collection `{}` is being rebuilt",
+            name
+        )));
+    }
+    let sample_size = sample_size.unwrap_or(DEFAULT_TRAINING_SAMPLE);
+    if sample_size == 0 {
+        return Err(Error::InvalidArgument(
+            "sample_size must be greater than 0".to_string(),
+        ));
+    }
+    Ok(sample_size)
+}
+
+/// Starts training the index of `name` in the background: a rebuild with
+/// unchanged settings, whose first [`rebuild_step`] trains on
+/// `sample_size` vectors. Searches fail until it is done, as with
+/// [`train_index`].
+pub fn start_training(conn: &Connection, name: &str, sample_size: Option<usize>) -> Result<()> {
+    let config = get_collection_config(conn, name)?;
+    let sample_size = check_trainable(name, &config, sample_size)?;
+    let has_points: bool = conn.query_row(
+        &format!("SELECT EXISTS (SELECT 1 FROM vss_{}_vectors)", name),
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_points {
+        return Err(Error::InvalidArgument(format!(
+            "collection `{}` has no points to train on",
+            name
+        )));
+    }
+    let rebuild = IndexRebuild {
+        index_factory: config.index_factory.clone(),
+        quantization_config: config.quantization_config.clone(),
+        trained: false,
+        copied_up_to: None,
+        points_copied: 0,
+        sample_size: Some(sample_size),
+    };
+    start_rebuild(conn, name, config, rebuild)
+}
+
+/// Starts rebuilding the dense index of `name` with the settings of
+/// `update`. Searches keep using the current index while
+/// [`rebuild_step`] fills the new one, which then replaces it. An update
+/// during a rebuild starts over.
+pub fn update_collection(conn: &Connection, name: &str, update: &CollectionUpdate) -> Result<()> {
+    let config = get_collection_config(conn, name)?;
+    if vector_columns(&config).is_empty() {
+        return Err(Error::InvalidArgument(format!(
+            "collection `{}` has no dense vectors to index",
+            name
+        )));
+    }
+    if let Some(factory) = &update.index_factory {
+        check_index_factory(factory)?;
+        if update.quantization_config.is_some() {
+            return Err(Error::InvalidArgument(
+                "index_factory and quantization_config are mutually exclusive".to_string(),
+            ));
+        }
+    }
+    let rebuild = IndexRebuild {
+        index_factory: update.index_factory.clone(),
+        quantization_config: update.quantization_config.clone(),
+        trained: false,
+        copied_up_to: None,
+        points_copied: 0,
+        sample_size: None,
+    };
+    start_rebuild(conn, name, config, rebuild)
+}
+
+/// Replaces any pending rebuild of `name` by `rebuild` and creates the
+/// vss0 table it fills.
+fn start_rebuild(
+    conn: &Connection,
+    name: &str,
+    mut config: CollectionConfig,
+    rebuild: IndexRebuild,
+) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    if let Some(pending) = &config.rebuild {
+        tx.execute_batch(&format!(
+            "DROP TABLE {};",
+            index_table(name, &config.rebuilt(pending))
+        ))?;
+    }
+    // Only creates the dense table, the multivector ones exist already.
+    tx.execute_batch(&index_tables(name, &config.rebuilt(&rebuild)))?;
+    config.rebuild = Some(rebuild);
+    save_collection_config(&tx, name, &config)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Drops the pending rebuild of `name`, if any, leaving the current index
+/// as it is. Does not open a transaction of its own.
+pub fn abort_rebuild(conn: &Connection, name: &str) -> Result<()> {
+    let mut config = get_collection_config(conn, name)?;
+    if let Some(rebuild) = config.rebuild.take() {
+        conn.execute_batch(&format!(
+            "DROP TABLE {};",
+            index_table(name, &config.rebuilt(&rebuild))
+        ))?;
+        save_collection_config(conn, name, &config)?;
+    }
+    Ok(())
+}
+
+/// Moves the rebuild of `name` forward by one step: trains the new index,
+/// copies the next `batch_size` points into it, or swaps it in once every
+/// point is copied. Returns whether the rebuild is done, which it also is
+/// when there was none. Runs in the caller's transaction, see
+/// [`jobs::step`].
+pub fn rebuild_step(tx: &Connection, name: &str, batch_size: usize) -> Result<bool> {
+    let mut config = get_collection_config(tx, name)?;
+    let Some(mut rebuild) = config.rebuild.clone() else {
+        return Ok(true);
+    };
+    let rebuilt = config.rebuilt(&rebuild);
+    let columns = vector_columns(&config);
+    let table = index_table(name, &rebuilt);
+    let vectors = vector_source(&config, name);
+
+    if rebuilt.needs_training() {
+        let sample_size = rebuild.sample_size.unwrap_or(DEFAULT_TRAINING_SAMPLE);
+        if train_from(tx, &columns, &table, &vectors, sample_size)? == 0 {
+            // No points: the next upsert or `train` trains the new index.
+            swap_index(tx, name, &config, &rebuilt)?;
+            return Ok(true);
+        }
+        rebuild.trained = true;
+    } else {
+        let ids = tx
+            .prepare(&format!(
+                "SELECT rowid FROM vss_{}_payload WHERE rowid > ?1 ORDER BY rowid LIMIT ?2",
+                name
+            ))?
+            .query_map(
+                params![
+                    rebuild.copied_up_to.map_or(-1, |id| id as i64),
+                    sql_limit(batch_size)
+                ],
+                |row| row.get::<_, u64>(0),
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let Some(&last) = ids.last() else {
+            swap_index(tx, name, &config, &rebuilt)?;
+            return Ok(true);
+        };
+        let batch = format!("{} WHERE rowid in ({})", vectors, id_list(&ids)?);
+        index_from(tx, &columns, &table, &batch)?;
+        rebuild.copied_up_to = Some(last);
+        rebuild.points_copied += ids.len() as u64;
+    }
+    config.rebuild = Some(rebuild);
+    save_collection_config(tx, name, &config)?;
+    Ok(false)
+}
+
+/// Replaces the index of `config` by the rebuilt one, keeping the original
+/// vectors only when the new index needs them.
+fn swap_index(
+    conn: &Connection,
+    name: &str,
+    config: &CollectionConfig,
+    rebuilt: &CollectionConfig,
+) -> Result<()> {
+    if rebuilt.stores_original_vectors() && !config.stores_original_vectors() {
+        let originals = vector_columns(config)
+            .iter()
+            .map(|c| c.original_column())
+            .collect::<Vec<_>>()
+            .join(",");
+        conn.execute_batch(&data_tables(name, rebuilt))?;
+        conn.execute(
+            &format!(
+                "INSERT INTO vss_{}_vectors(rowid,{}) SELECT rowid,{} FROM ({})",
+                name,
+                originals,
+                originals,
+                vector_source(config, name)
+            ),
+            [],
+        )?;
+    } else if config.stores_original_vectors() && !rebuilt.stores_original_vectors() {
+        conn.execute_batch(&format!("DROP TABLE vss_{}_vectors;", name))?;
+    }
+    conn.execute_batch(&format!("DROP TABLE {};", index_table(name, config)))?;
+    save_collection_config(conn, name, rebuilt)
+}
+
+/// Collections with a rebuild in progress, e.g. to resume after a restart.
+pub fn pending_rebuilds(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT name FROM collections WHERE json_extract(config, '$.rebuild') IS NOT NULL",
+    )?;
+    let names = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(names)
+}
+
+/// Trains the vss0 table `table` on a random sample of `vectors`, a query
+/// shaped like [`vector_source`]. Returns the number of vectors sampled.
+fn train_from(
+    conn: &Connection,
+    columns: &[VectorColumn],
+    table: &str,
+    vectors: &str,
+    sample_size: usize,
+) -> Result<usize> {
+    let sql = format!(
+        r#"
+        INSERT INTO {}(operation,{})
+        SELECT 'training', {} FROM ({} ORDER BY random() LIMIT ?1);
+        "#,
+        table,
+        index_columns(columns),
+        from_raw_columns(columns),
+        vectors
+    );
+    Ok(conn.execute(
+        &sql,
+        params![i64::try_from(sample_size).unwrap_or(i64::MAX)],
+    )?)
+}
+
+/// Adds every vector selected by `vectors` to the vss0 table `table`.
+fn index_from(
+    conn: &Connection,
+    columns: &[VectorColumn],
+    table: &str,
+    vectors: &str,
+) -> Result<usize> {
+    let sql = format!(
+        "INSERT INTO {}(rowid,{}) SELECT rowid, {} FROM ({})",
+        table,
+        index_columns(columns),
+        from_raw_columns(columns),
+        vectors
+    );
+    Ok(conn.execute(&sql, [])?)
+}
+
+fn index_columns(columns: &[VectorColumn]) -> String {
+    columns
+        .iter()
+        .map(|c| c.index_column())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn from_raw_columns(columns: &[VectorColumn]) -> String {
+    columns
+        .iter()
+        .map(|c| format!("vector_from_raw({})", c.original_column()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[test]
+fn test_update_collection() {
+    use crate::{ScalarQuantization, ScalarType};
+
+    let conn = test_conn();
+    create_collections(&conn, "test_update", 2).unwrap();
+    let points = (1..=10)
+        .map(|i| Point {
+            id: i,
+            vector: vec![i as f32, 0.0].into(),
+            payload: None,
+        })
+        .collect::<Vec<_>>();
+    add_point(&conn, "test_update", &points).unwrap();
+
+    let update = CollectionUpdate {
+        index_factory: None,
+        quantization_config: Some(QuantizationConfig::Scalar(ScalarQuantization {
+            kind: ScalarType::Int8,
+        })),
+    };
+    update_collection(&conn, "test_update", &update).unwrap();
+    // Training, then two batches of four points.
+    for _ in 0..3 {
+        assert!(!rebuild_step(&conn, "test_update", 4).unwrap());
+    }
+    let info = get_collections_info(&conn, "test_update").unwrap();
+    assert_eq!(
+        info.rebuild,
+        Some(RebuildInfo {
+            index_factory: "SQ8,IDMap2".to_string(),
+            points_copied: 8,
+            points_count: 10,
+        })
+    );
+
+    // Writes during the rebuild reach both indexes.
+    add_point(
+        &conn,
+        "test_update",
+        &[Point {
+            id: 2,
+            vector: vec![20.0, 0.0].into(),
+            payload: None,
+        }],
+    )
+    .unwrap();
+    delete_points(&conn, "test_update", vec![3]).unwrap();
+    let hits = search_points(&conn, "test_update", &[19.0, 0.0], 1).unwrap();
+    assert_eq!(hits[0].id, 2);
+
+    while !rebuild_step(&conn, "test_update", 4).unwrap() {}
+    let config = get_collection_config(&conn, "test_update").unwrap();
+    assert_eq!(config.quantization_config, update.quantization_config);
+    assert!(config.index_trained && config.rebuild.is_none());
+    assert_eq!(config.index_generation, 1);
+    let info = get_collections_info(&conn, "test_update").unwrap();
+    assert_eq!(info.points_count, 9);
+    assert!(info.rebuild.is_none());
+    let hits = search_points(&conn, "test_update", &[19.0, 0.0], 2).unwrap();
+    assert_eq!(hits.iter().map(|p| p.id).collect::<Vec<_>>(), vec![2, 10]);
+    let hits = search_points(&conn, "test_update", &[3.0, 0.0], 1).unwrap();
+    assert_eq!(hits[0].id, 4);
+    assert_eq!(
+        get_point(&conn, "test_update", 5).unwrap().unwrap().vector,
+        points[4].vector
+    );
+
+    // The old vss0 table is gone.
+    let tables: u64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE name = 'vss_test_update'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(tables, 0);
+
+    // Reopening doesn't take the rebuilt index for a legacy collection.
+    init_schema(&conn).unwrap();
+    assert!(matches!(
+        get_collection_config(&conn, "test_update_g1"),
+        Err(Error::CollectionNotFound(_))
+    ));
+    delete_collection(&conn, "test_update").unwrap();
+}
+
+/// Orders heap entries by distance, so a max-heap keeps the `k` nearest.
+struct Neighbor(f32, u64);
+
+impl PartialEq for Neighbor {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Neighbor {}
+
+impl PartialOrd for Neighbor {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Neighbor {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+/// Brute-force top-`limit` for every query in a single pass over the stored
+/// vectors matching `filter`. Returns `(id, squared L2)` pairs, nearest
+/// first. Works on untrained indexes too, since it never touches vss0.
+fn exact_search(
+    conn: &Connection,
+    config: &CollectionConfig,
+    name: &str,
+    column: &VectorColumn,
+    queries: &[Vec<f32>],
+    limit: usize,
+    filter: Option<&SqlFilter>,
+) -> Result<Vec<Vec<(u64, f32)>>> {
+    let mut heaps = queries
+        .iter()
+        .map(|_| BinaryHeap::with_capacity(limit + 1))
+        .collect::<Vec<_>>();
+    let (sql, values) = match filter {
+        None => (column_source(config, name, column), vec![]),
+        Some(filter) => (
+            format!(
+                "SELECT s.* FROM ({}) s JOIN vss_{}_payload p ON p.rowid = s.rowid WHERE {}",
+                column_source(config, name, column),
+                name,
+                filter.sql
+            ),
+            filter.params.clone(),
+        ),
+    };
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(params_from_iter(values))?;
+    while let Some(row) = rows.next()? {
+        let id: u64 = row.get(0)?;
+        let vector = blob_to_vector(&row.get::<_, Vec<u8>>(1)?)?;
+        for (query, heap) in queries.iter().zip(heaps.iter_mut()) {
+            let distance = l2_squared(&vector, query);
+            if heap.len() < limit {
+                heap.push(Neighbor(distance, id));
+            } else if heap.peek().is_some_and(|n| distance < n.0) {
+                heap.pop();
+                heap.push(Neighbor(distance, id));
+            }
+        }
+    }
+    Ok(heaps
+        .into_iter()
+        .map(|heap| {
+            heap.into_sorted_vec()
+                .into_iter()
+                .map(|Neighbor(distance, id)| (id, distance))
+                .collect()
+        })
+        .collect())
+}
+
+/// Uses stored points as queries and measures how many of the exact top-k
+/// the index search returns with the given search params.
+pub fn evaluate(
+    conn: &Connection,
+    name: &str,
+    request: &EvaluateRequest,
+) -> Result<EvaluationReport> {
+    let config = get_collection_config(conn, name)?;
+    let column = column_by_name(&config, request.using.as_deref())?;
+    if request.sample_size == 0 || request.limit == 0 {
+        return Err(Error::InvalidArgument(
+            "sample_size and limit must be greater than 0".to_string(),
+        ));
+    }
+
+    let sql = format!(
+        "{} ORDER BY random() LIMIT ?1",
+        column_source(&config, name, &column)
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let queries = stmt
+        .query_map(
+            params![i64::try_from(request.sample_size).unwrap_or(i64::MAX)],
+            |row| row.get::<_, Vec<u8>>(1),
+        )?
+        .map(|raw| blob_to_vector(&raw?))
+        .collect::<Result<Vec<_>>>()?;
+    if queries.is_empty() {
+        return Err(Error::InvalidArgument(format!(
+            "collection `{}` has no points to evaluate",
+            name
+        )));
+    }
+
+    let started = Instant::now();
+    let mut approximate = Vec::with_capacity(queries.len());
+    for query in &queries {
+        let vector = match &request.using {
+            Some(name) => NamedVectorStruct::Named(NamedVector {
+                name: name.clone(),
+                vector: query.clone(),
+            }),
+            None => NamedVectorStruct::Default(query.clone()),
+        };
+        let search_request = SearchRequest {
+            vector,
+            limit: request.limit,
+            filter: None,
+            params: request.params.clone(),
+            mmr: None,
+        };
+        approximate.push(search(conn, name, &search_request)?);
+    }
+    let approximate_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    let started = Instant::now();
+    let exact = exact_search(conn, &config, name, &column, &queries, request.limit, None)?;
+    let exact_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    let recalls = approximate
+        .iter()
+        .zip(&exact)
+        .map(|(approximate, exact)| {
+            if exact.is_empty() {
+                return 1.0;
+            }
+            let found = exact
+                .iter()
+                .filter(|(id, _)| approximate.iter().any(|p| p.id == *id))
+                .count();
+            found as f64 / exact.len() as f64
+        })
+        .collect::<Vec<f64>>();
+
+    let n = queries.len() as f64;
+    Ok(EvaluationReport {
+        index_factory: config.index_factory(),
+        sample_size: queries.len(),
+        limit: request.limit,
+        recall: recalls.iter().sum::<f64>() / n,
+        min_recall: recalls.iter().copied().fold(1.0, f64::min),
+        approximate_ms: approximate_ms / n,
+        exact_ms: exact_ms / n,
+    })
+}
+
+#[test]
+fn test_train_and_evaluate() {
+    let conn = test_conn();
+    let mut config = CollectionConfig::new(8);
+    config.index_factory = Some("IVF4,PQ2x4".to_string());
+    create_collection_with_config(&conn, "test_ivfpq", &config).unwrap();
+
+    let points = (0..256)
+        .map(|i| Point {
+            id: i,
+            vector: (0..8)
+                .map(|d| match d {
+                    0 => i as f32 / 256.0,
+                    _ => ((i * 7 + d * 13) % 32) as f32 / 32.0,
+                })
+                .collect::<Vec<f32>>()
+                .into(),
+            payload: None,
+        })
+        .collect::<Vec<_>>();
+    add_point(&conn, "test_ivfpq", &points).unwrap();
+    let vector = |i: usize| points[i].vector.get(None).unwrap().to_vec();
+    assert_eq!(
+        get_collections_info(&conn, "test_ivfpq")
+            .unwrap()
+            .points_count,
+        256
+    );
+    assert!(matches!(
+        search_points(&conn, "test_ivfpq", &vector(0), 5),
+        Err(Error::IndexNotTrained(_))
+    ));
+
+    let report = train_index(&conn, "test_ivfpq", None).unwrap();
+    assert_eq!(report.index_factory, "IVF4,PQ2x4,IDMap2");
+    assert_eq!(report.trained_on, 256);
+    assert_eq!(report.points_indexed, 256);
+    assert!(matches!(
+        train_index(&conn, "test_ivfpq", None),
+        Err(Error::InvalidArgument(_))
+    ));
+
+    let r = search_points(&conn, "test_ivfpq", &vector(3), 5).unwrap();
+    assert_eq!(r.len(), 5);
+    assert_eq!(r[0].vector, points[r[0].id as usize].vector);
+
+    let mut request = SearchRequest::new(vector(3), 5);
+    request.params.exact = true;
+    let r = search(&conn, "test_ivfpq", &request).unwrap();
+    assert_eq!(r[0].id, 3);
+    assert_eq!(r[0].score, 0.0);
+
+    let request = EvaluateRequest {
+        sample_size: 16,
+        limit: 5,
+        using: None,
+        params: Default::default(),
+    };
+    let report = evaluate(&conn, "test_ivfpq", &request).unwrap();
+    assert_eq!(report.sample_size, 16);
+    assert!(report.recall > 0.0 && report.recall <= 1.0);
+    assert!(report.min_recall <= report.recall);
+
+    // A flat index is exact, and every query finds itself first.
+    create_collections(&conn, "test_flat", 8).unwrap();
+    add_point(&conn, "test_flat", &points).unwrap();
+    let request = EvaluateRequest {
+        limit: 1,
+        ..request
+    };
+    let report = evaluate(&conn, "test_flat", &request).unwrap();
+    assert_eq!(report.recall, 1.0);
+
+    // A rename keeps the trained index as it is.
+    let stored_index = |table: &str| -> Vec<u8> {
+        conn.query_row(&format!("SELECT idx FROM {}_index", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    };
+    let config = get_collection_config(&conn, "test_ivfpq").unwrap();
+    let trained = stored_index(&index_table("test_ivfpq", &config));
+    let hits = search_points(&conn, "test_ivfpq", &vector(5), 5).unwrap();
+    rename_collection(&conn, "test_ivfpq", "test_pq").unwrap();
+    assert!(
+        get_collection_config(&conn, "test_pq")
+            .unwrap()
+            .index_trained
+    );
+    assert_eq!(stored_index("vss_test_pq"), trained);
+    assert_eq!(
+        search_points(&conn, "test_pq", &vector(5), 5).unwrap(),
+        hits
+    );
+}
+
+pub fn delete_points(conn: &Connection, name: &str, ids: Vec<u64>) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    remove_points(&tx, name, &ids)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// [`delete_points`] inside the caller's transaction.
+fn remove_points(tx: &Connection, name: &str, ids: &[u64]) -> Result<()> {
+    let config = get_collection_config(tx, name)?;
+    let ids = id_list(ids)?;
+
+    let mut sql = format!("DELETE FROM vss_{}_payload WHERE rowid in ({});", name, ids);
+    if !vector_columns(&config).is_empty() {
+        sql.push_str(&format!(
+            "DELETE FROM {} WHERE rowid in ({});",
+            index_table(name, &config),
+            ids
+        ));
+    }
+    if let Some(rebuild) = &config.rebuild {
+        sql.push_str(&format!(
+            "DELETE FROM {} WHERE rowid in ({});",
+            index_table(name, &config.rebuilt(rebuild)),
+            ids
+        ));
+    }
+    if config.stores_original_vectors() {
+        sql.push_str(&format!(
+            "DELETE FROM vss_{}_vectors WHERE rowid in ({});",
+            name, ids
+        ));
+    }
+    if !config.sparse_vectors.is_empty() {
+        sql.push_str(&format!(
+            "DELETE FROM vss_{}_sparse WHERE point_id in ({});",
+            name, ids
+        ));
+    }
+    tx.execute_batch(sql.as_str())?;
+    for vector in config.multivectors.keys() {
+        delete_tokens(tx, name, vector, &ids)?;
+    }
+    for key in geo_keys(&config) {
+        tx.execute_batch(&format!(
+            "DELETE FROM {} WHERE id in ({});",
+            geo_index_name(name, key),
+            ids
+        ))?;
+    }
+    Ok(())
+}
+
+/// Applies `operations` to collection `name` inside the caller's
+/// transaction. vss0 applies the deletes of a transaction before its
+/// inserts, so the operations are first replayed in memory on the points
+/// they touch. Each point's outcome is then written once: points whose
+/// vectors were written are deleted or upserted whole, the others only get
+/// their new payload.
+fn apply_operations(tx: &Connection, name: &str, operations: &[PointOperation]) -> Result<()> {
+    check_write(tx, name, operations)?;
+    let config = get_collection_config(tx, name)?;
+
+    // Touched ids in order, and whether their vectors are written. Points
+    // first touched by an upsert or a delete don't need their stored state.
+    let mut touched: Vec<u64> = vec![];
+    let mut vectors_written = HashMap::new();
+    let mut load = vec![];
+    for operation in operations {
+        let writes_vectors = !operation.vector_ids().is_empty();
+        let replaces = matches!(
+            operation,
+            PointOperation::Upsert { .. } | PointOperation::Delete { .. }
+        );
+        for id in operation_ids(operation) {
+            let written = vectors_written.entry(id).or_insert_with(|| {
+                touched.push(id);
+                if !replaces {
+                    load.push(id);
+                }
+                false
+            });
+            *written |= writes_vectors;
+        }
+    }
+    let (load_points, load_payloads): (Vec<u64>, Vec<u64>) =
+        load.into_iter().partition(|id| vectors_written[id]);
+    let mut points = get_points(tx, name, load_points)?
+        .into_iter()
+        .map(|point| (point.id, Some(point)))
+        .collect::<HashMap<_, _>>();
+    for (id, payload) in get_payloads(tx, name, &id_list(&load_payloads)?)? {
+        points.insert(
+            id,
+            Some(Point {
+                id,
+                vector: VectorStruct::default(),
+                payload,
+            }),
+        );
     }
 
-    for v in payload_r {
-        if let Ok((id, payload)) = v {
-            if let Some(point) = map.get_mut(&id) {
-                point.payload = payload;
+    for operation in operations {
+        match operation {
+            PointOperation::Upsert { points: upserted } => {
+                for point in upserted {
+                    points.insert(point.id, Some(point.clone()));
+                }
+            }
+            PointOperation::Delete { points: deleted } => {
+                for &id in deleted {
+                    points.insert(id, None);
+                }
+            }
+            PointOperation::SetPayload {
+                payload,
+                points: ids,
+            } => {
+                for &id in ids {
+                    stored(&mut points, id)?
+                        .payload
+                        .get_or_insert_with(Payload::new)
+                        .extend(payload.clone());
+                }
+            }
+            PointOperation::DeletePayload { keys, points: ids } => {
+                for &id in ids {
+                    if let Some(payload) = &mut stored(&mut points, id)?.payload {
+                        for key in keys {
+                            payload.remove(key);
+                        }
+                    }
+                }
+            }
+            PointOperation::ClearPayload { points: ids } => {
+                for &id in ids {
+                    stored(&mut points, id)?.payload = None;
+                }
+            }
+            PointOperation::UpdateVectors { points: updates } => {
+                for update in updates {
+                    let point = stored(&mut points, update.id)?;
+                    match (&mut point.vector, &update.vector) {
+                        (VectorStruct::Single(current), VectorStruct::Single(vector)) => {
+                            *current = vector.clone()
+                        }
+                        (current, VectorStruct::Single(vector)) => {
+                            current.insert(String::new(), vector.clone().into())
+                        }
+                        (current, VectorStruct::Named(vectors)) => {
+                            for (vector_name, vector) in vectors {
+                                current.insert(vector_name.clone(), vector.clone());
+                            }
+                        }
+                    }
+                }
             }
         }
     }
 
-    Ok(map.into_iter().map(|(_, v)| v).collect())
+    let mut deleted = vec![];
+    let mut upserted = vec![];
+    let mut payload_stmt = tx.prepare(&format!(
+        "UPDATE vss_{}_payload SET payload = ?2 WHERE rowid = ?1",
+        name
+    ))?;
+    for id in touched {
+        match (vectors_written[&id], points.remove(&id).flatten()) {
+            (true, Some(point)) => upserted.push(point),
+            (true, None) => deleted.push(id),
+            (false, Some(point)) => {
+                payload_stmt.execute(params![id, serde_json::to_string(&point.payload)?])?;
+                for key in geo_keys(&config) {
+                    index_location(tx, name, key, id, point.payload.as_ref())?;
+                }
+            }
+            // Payload operations already fail on missing points.
+            (false, None) => return Err(Error::PointNotFound(id)),
+        }
+    }
+    remove_points(tx, name, &deleted)?;
+    upsert_points(tx, name, &upserted)?;
+    Ok(())
 }
 
-pub fn get_point(conn: &Connection, name: &str, id: u64) -> rusqlite::Result<Point> {
-    let point_sql = format!(
-        r#"
-        SELECT rowid,vector_to_raw(point) FROM vss_{} WHERE rowid = ?1;
-        "#,
-        name
-    );
+/// The point `id` as the operations so far left it, which must exist.
+fn stored(points: &mut HashMap<u64, Option<Point>>, id: u64) -> Result<&mut Point> {
+    points
+        .get_mut(&id)
+        .and_then(Option::as_mut)
+        .ok_or(Error::PointNotFound(id))
+}
 
-    let payload_sql = format!(
-        r#"
-        SELECT * FROM vss_{}_payload WHERE rowid = ?1;
-        "#,
-        name
-    );
+fn operation_ids(operation: &PointOperation) -> Vec<u64> {
+    match operation {
+        PointOperation::SetPayload { points, .. }
+        | PointOperation::DeletePayload { points, .. }
+        | PointOperation::ClearPayload { points } => points.clone(),
+        _ => operation.vector_ids(),
+    }
+}
 
-    let mut point_stmt = conn.prepare(point_sql.as_str())?;
-    let mut payload_stmt = conn.prepare(payload_sql.as_str())?;
+/// Checks what can be checked of `operations` without applying them: that
+/// collection `name` exists, and that ids and vectors are valid for it.
+/// Lets queued writes fail before they are acknowledged.
+pub fn check_write(conn: &Connection, name: &str, operations: &[PointOperation]) -> Result<()> {
+    let config = get_collection_config(conn, name)?;
+    let columns = vector_columns(&config);
+    for operation in operations {
+        match operation {
+            PointOperation::Upsert { points } => {
+                for point in points {
+                    check_id(point.id)?;
+                    check_point_vectors(&config, &point.vector)?;
+                }
+            }
+            PointOperation::Delete { points }
+            | PointOperation::SetPayload { points, .. }
+            | PointOperation::DeletePayload { points, .. }
+            | PointOperation::ClearPayload { points } => {
+                for &id in points {
+                    check_id(id)?;
+                }
+            }
+            PointOperation::UpdateVectors { points } => {
+                for update in points {
+                    check_id(update.id)?;
+                    match &update.vector {
+                        VectorStruct::Single(vector) => {
+                            check_dimension(&column_by_name(&config, None)?, vector)?
+                        }
+                        VectorStruct::Named(named) => {
+                            for (vector_name, vector) in named {
+                                check_named_vector(&config, &columns, vector_name, vector)?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
 
-    let vector = point_stmt.query_row(params![id], |row| {
-        let vector_raw: Vec<u8> = row.get(1)?;
-        let vector: Vec<f32> = blob_to_vector(&vector_raw);
-        Ok(vector)
-    })?;
+/// Applies `operations` to collection `name` in order and in one
+/// transaction: all of them, or none if one fails.
+pub fn apply_batch(conn: &Connection, name: &str, operations: &[PointOperation]) -> Result<()> {
+    apply_group(conn, &[(name, operations)])
+}
 
-    let payload = payload_stmt.query_row(params![id], |row| {
-        let payload_str: String = row.get(1)?;
-        let payload: Option<serde_json::Map<String, serde_json::Value>> =
-            serde_json::from_str(&payload_str).unwrap_or_default();
-        Ok(payload)
-    })?;
+/// Applies `writes`, `(collection, operations)` pairs, in order with as
+/// few commits as possible: consecutive writes share a transaction. Since
+/// vss0 applies the deletes of a transaction before its inserts, a write
+/// touching the vectors of a point that an earlier write in the
+/// transaction touched starts a new one. When a
+/// shared transaction fails, its writes are retried in one transaction
+/// each, so that a bad write does not fail the others. Every write is
+/// applied entirely or not at all; returns the result of each.
+pub fn apply_writes(conn: &Connection, writes: &[(&str, &[PointOperation])]) -> Vec<Result<()>> {
+    let mut results = Vec::with_capacity(writes.len());
+    let mut start = 0;
+    while start < writes.len() {
+        let mut touched = HashSet::new();
+        let mut end = start;
+        while let Some((name, operations)) = writes.get(end) {
+            let ids = operations
+                .iter()
+                .flat_map(|operation| operation.vector_ids())
+                .collect::<Vec<_>>();
+            if end > start && ids.iter().any(|&id| touched.contains(&(*name, id))) {
+                break;
+            }
+            touched.extend(ids.into_iter().map(|id| (*name, id)));
+            end += 1;
+        }
+        let group = &writes[start..end];
+        match apply_group(conn, group) {
+            Ok(()) => results.extend(group.iter().map(|_| Ok(()))),
+            Err(e) if group.len() == 1 => results.push(Err(e)),
+            Err(_) => results.extend(
+                group
+                    .iter()
+                    .map(|write| apply_group(conn, std::slice::from_ref(write))),
+            ),
+        }
+        start = end;
+    }
+    results
+}
 
-    Ok(Point {
-        id,
-        vector,
-        payload,
-    })
+fn apply_group(conn: &Connection, writes: &[(&str, &[PointOperation])]) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for (name, operations) in writes {
+        apply_operations(&tx, name, operations)?;
+    }
+    tx.commit()?;
+    Ok(())
 }
 
 #[test]
-fn test_points_base() {
+fn test_points_delete() {
     use serde_json::json;
-    init();
-    let conn = rusqlite::Connection::open_in_memory().unwrap();
+    let conn = test_conn();
     create_collections(&conn, "test_vss", 4).unwrap();
     let mut points = Vec::<Point>::new();
     {
         points.push(Point {
             id: 1,
-            vector: vec![0.05, 0.61, 0.76, 0.74],
+            vector: vec![0.05, 0.61, 0.76, 0.74].into(),
             payload: json!({"city": "Berlin"}).as_object().map(|m| m.to_owned()),
         });
         points.push(Point {
             id: 2,
-            vector: vec![0.19, 0.81, 0.75, 0.11],
+            vector: vec![0.19, 0.81, 0.75, 0.11].into(),
             payload: json!({"city": "London"}).as_object().map(|m| m.to_owned()),
         });
         points.push(Point {
             id: 3,
-            vector: vec![0.36, 0.55, 0.47, 0.94],
+            vector: vec![0.36, 0.55, 0.47, 0.94].into(),
             payload: json!({"city": "Moscow"}).as_object().map(|m| m.to_owned()),
         });
         points.push(Point {
             id: 4,
-            vector: vec![0.18, 0.01, 0.85, 0.80],
+            vector: vec![0.18, 0.01, 0.85, 0.80].into(),
             payload: json!({"city": "New York"})
                 .as_object()
                 .map(|m| m.to_owned()),
         });
         points.push(Point {
             id: 5,
-            vector: vec![0.24, 0.18, 0.22, 0.44],
+            vector: vec![0.24, 0.18, 0.22, 0.44].into(),
             payload: json!({"city": "Beijing"}).as_object().map(|m| m.to_owned()),
         });
         points.push(Point {
             id: 6,
-            vector: vec![0.35, 0.08, 0.11, 0.44],
+            vector: vec![0.35, 0.08, 0.11, 0.44].into(),
             payload: json!({"city": "Mumbai"}).as_object().map(|m| m.to_owned()),
         });
     }
     let r = add_point(&conn, "test_vss", &points).unwrap();
     assert_eq!(r, vec![1, 2, 3, 4, 5, 6]);
 
-    let r = add_point(&conn, "test_vss", &points[1..]).unwrap();
-    assert_eq!(r, vec![2, 3, 4, 5, 6]);
+    delete_points(&conn, "test_vss", vec![1, 2, 3, 4]).unwrap();
 
-    let mut r = get_points(&conn, "test_vss", vec![1, 2, 3]).unwrap();
-    assert_eq!(r.len(), 3);
-    r.sort_by(|a, b| a.id.cmp(&b.id));
-    assert_eq!(r[0].payload, points[0].payload);
-    assert_eq!(r[1].payload, points[1].payload);
-    assert_eq!(r[2].payload, points[2].payload);
+    let r = get_points(&conn, "test_vss", vec![1, 2, 3, 4]).unwrap();
+    assert_eq!(r.len(), 0);
+}
 
-    let r = get_point(&conn, "test_vss", 4).unwrap();
-    assert_eq!(r.payload, points[3].payload);
+#[test]
+fn test_apply_writes() {
+    use crate::PointVectors;
+    let conn = test_conn();
+    create_collections(&conn, "test_writes", 2).unwrap();
+    let point = |id: u64, x: f32| Point {
+        id,
+        vector: vec![x, 0.0].into(),
+        payload: None,
+    };
+    let upsert = |points| PointOperation::Upsert { points };
+    let writes = [
+        upsert(vec![point(1, 1.0), point(2, 2.0)]),
+        upsert(vec![point(3, 3.0)]),
+        // Touches point 1 again, so it is committed separately.
+        PointOperation::Delete { points: vec![1] },
+        upsert(vec![point(4, 4.0)]),
+        // Fails alone, its group is retried write by write.
+        upsert(vec![Point {
+            id: 5,
+            vector: vec![5.0].into(),
+            payload: None,
+        }]),
+        upsert(vec![point(6, 6.0)]),
+    ];
+    let writes = writes
+        .iter()
+        .map(|operation| ("test_writes", std::slice::from_ref(operation)))
+        .collect::<Vec<_>>();
+    let results = apply_writes(&conn, &writes);
+    assert_eq!(
+        results.iter().map(Result::is_ok).collect::<Vec<_>>(),
+        vec![true, true, true, true, false, true]
+    );
+    assert!(matches!(results[4], Err(Error::DimensionMismatch { .. })));
+
+    let ids = |r: Vec<ScoredPoint>| r.into_iter().map(|p| p.id).collect::<Vec<_>>();
+    assert_eq!(
+        ids(search_points(&conn, "test_writes", &[0.0, 0.0], 10).unwrap()),
+        vec![2, 3, 4, 6]
+    );
+    assert!(matches!(
+        apply_writes(&conn, &[("test_missing", writes[1].1)])[0],
+        Err(Error::CollectionNotFound(_))
+    ));
+
+    // What a write would fail on is caught before it is queued.
+    check_write(&conn, "test_writes", writes[1].1).unwrap();
+    assert!(matches!(
+        check_write(&conn, "test_writes", writes[4].1),
+        Err(Error::DimensionMismatch { .. })
+    ));
+    assert!(matches!(
+        check_write(&conn, "test_missing", writes[1].1),
+        Err(Error::CollectionNotFound(_))
+    ));
+    let update = PointOperation::UpdateVectors {
+        points: vec![PointVectors {
+            id: 2,
+            vector: vec![1.0].into(),
+        }],
+    };
+    assert!(matches!(
+        check_write(&conn, "test_writes", &[update]),
+        Err(Error::DimensionMismatch { .. })
+    ));
 }
 
-pub fn search_points(
-    conn: &Connection,
-    name: &str,
-    vector: &[f32],
-    limit: usize,
-) -> rusqlite::Result<Vec<ScoredPoint>> {
-    let sql = format!(
-        r#"
-        SELECT rowid,vector_to_raw(point),distance FROM vss_{} WHERE vss_search(point,vector_from_raw(?1)) ORDER BY distance LIMIT ?2;
-        "#,
-        name
+#[test]
+fn test_apply_batch() {
+    use crate::PointVectors;
+    use serde_json::json;
+
+    let conn = test_conn();
+    create_collections(&conn, "test_batch", 2).unwrap();
+    create_field_index(&conn, "test_batch", "location", PayloadSchemaType::Geo).unwrap();
+    let payload = |value: serde_json::Value| value.as_object().cloned();
+    let points = (1..=3)
+        .map(|i| Point {
+            id: i,
+            vector: vec![i as f32, 0.0].into(),
+            payload: payload(json!({"city": "Berlin", "rank": i})),
+        })
+        .collect::<Vec<_>>();
+    add_point(&conn, "test_batch", &points).unwrap();
+
+    let operations = [
+        PointOperation::Upsert {
+            points: vec![Point {
+                id: 4,
+                vector: vec![4.0, 0.0].into(),
+                payload: None,
+            }],
+        },
+        PointOperation::Delete { points: vec![3] },
+        PointOperation::SetPayload {
+            payload: payload(json!({"location": {"lat": 52.52, "lon": 13.405}})).unwrap(),
+            points: vec![1, 4],
+        },
+        PointOperation::DeletePayload {
+            keys: vec!["rank".to_string()],
+            points: vec![1],
+        },
+        PointOperation::ClearPayload { points: vec![2] },
+        PointOperation::UpdateVectors {
+            points: vec![PointVectors {
+                id: 2,
+                vector: vec![9.0, 0.0].into(),
+            }],
+        },
+    ];
+    apply_batch(&conn, "test_batch", &operations).unwrap();
+    let location = json!({"lat": 52.52, "lon": 13.405});
+    let point = |id| get_point(&conn, "test_batch", id).unwrap();
+    assert_eq!(
+        point(1).unwrap().payload,
+        payload(json!({"city": "Berlin", "location": location}))
+    );
+    assert_eq!(point(2).unwrap().payload, None);
+    assert_eq!(point(2).unwrap().vector, vec![9.0, 0.0].into());
+    assert!(point(3).is_none());
+    assert_eq!(
+        point(4).unwrap().payload,
+        payload(json!({"location": location}))
+    );
+    let near_berlin = json!({"must": [{
+        "key": "location",
+        "geo_radius": {"center": {"lat": 52.5, "lon": 13.4}, "radius": 10_000.0}
+    }]});
+    let request = ScrollRequest {
+        filter: Some(serde_json::from_value(near_berlin).unwrap()),
+        ..Default::default()
+    };
+    let result = scroll(&conn, "test_batch", &request).unwrap();
+    assert_eq!(
+        result.points.iter().map(|p| p.id).collect::<Vec<_>>(),
+        vec![1, 4]
     );
 
-    let mut stmt = conn.prepare(sql.as_str())?;
-    let vector_raw = vector_to_blob(&vector);
-    let points = stmt.query_map(params![vector_raw, limit], |row| {
-        let id: u64 = row.get(0)?;
-        let vector_raw: Vec<u8> = row.get(1)?;
-        let score: f32 = row.get(2)?;
-        let vector: Vec<f32> = blob_to_vector(&vector_raw);
-        Ok(ScoredPoint {
+    // A failing operation rolls back the ones before it.
+    let operations = [
+        PointOperation::Delete { points: vec![1] },
+        PointOperation::ClearPayload { points: vec![3] },
+    ];
+    assert!(matches!(
+        apply_batch(&conn, "test_batch", &operations),
+        Err(Error::PointNotFound(3))
+    ));
+    assert!(point(1).is_some());
+
+    // Operations on the same point apply in order.
+    let upsert = |id, x| PointOperation::Upsert {
+        points: vec![Point {
             id,
-            vector,
+            vector: vec![x, 0.0].into(),
+            payload: payload(json!({"rank": id})),
+        }],
+    };
+    let operations = [
+        upsert(5, 5.0),
+        PointOperation::UpdateVectors {
+            points: vec![PointVectors {
+                id: 5,
+                vector: vec![50.0, 0.0].into(),
+            }],
+        },
+        PointOperation::ClearPayload { points: vec![5] },
+        PointOperation::Delete { points: vec![4] },
+        upsert(4, 40.0),
+        upsert(6, 6.0),
+        PointOperation::Delete { points: vec![6] },
+        PointOperation::Delete { points: vec![1] },
+        PointOperation::UpdateVectors {
+            points: vec![PointVectors {
+                id: 1,
+                vector: vec![1.0, 1.0].into(),
+            }],
+        },
+    ];
+    assert!(matches!(
+        apply_batch(&conn, "test_batch", &operations),
+        Err(Error::PointNotFound(1))
+    ));
+    apply_batch(&conn, "test_batch", &operations[..7]).unwrap();
+    assert_eq!(
+        point(5),
+        Some(Point {
+            id: 5,
+            vector: vec![50.0, 0.0].into(),
             payload: None,
-            score,
         })
-    })?;
+    );
+    assert_eq!(point(4).unwrap().vector, vec![40.0, 0.0].into());
+    assert_eq!(point(4).unwrap().payload, payload(json!({"rank": 4})));
+    assert!(point(6).is_none());
+    let hits = search_points(&conn, "test_batch", &[46.0, 0.0], 3).unwrap();
+    assert_eq!(hits.iter().map(|p| p.id).collect::<Vec<_>>(), vec![5, 4, 2]);
+    // Point 4 left the geo index when it was replaced.
+    let result = scroll(&conn, "test_batch", &request).unwrap();
+    assert_eq!(
+        result.points.iter().map(|p| p.id).collect::<Vec<_>>(),
+        vec![1]
+    );
+}
 
-    let mut map = HashMap::new();
-    for point in points {
-        if let Ok(point) = point {
-            map.insert(point.id, point);
+pub fn delete_collection(conn: &Connection, name: &str) -> Result<()> {
+    let config = get_collection_config(conn, name)?;
+    let mut sql = format!(
+        r#"
+        DROP TABLE IF EXISTS {};
+        DROP TABLE IF EXISTS vss_{}_payload;
+        DROP TABLE IF EXISTS vss_{}_vectors;
+        DROP TABLE IF EXISTS vss_{}_sparse;
+        "#,
+        index_table(name, &config),
+        name,
+        name,
+        name
+    );
+    if let Some(rebuild) = &config.rebuild {
+        sql.push_str(&format!(
+            "DROP TABLE IF EXISTS {};",
+            index_table(name, &config.rebuilt(rebuild))
+        ));
+    }
+    for vector in config.multivectors.keys() {
+        let (index, tokens) = multivector_tables(name, vector);
+        sql.push_str(&format!(
+            "DROP TABLE IF EXISTS {}; DROP TABLE IF EXISTS {};",
+            index, tokens
+        ));
+    }
+    for key in geo_keys(&config) {
+        sql.push_str(&format!(
+            "DROP TABLE IF EXISTS {};",
+            geo_index_name(name, key)
+        ));
+    }
+    let tx = conn.unchecked_transaction()?;
+    tx.execute_batch(sql.as_str())?;
+    tx.execute("DELETE FROM collections WHERE name = ?1", params![name])?;
+    tx.execute("DELETE FROM aliases WHERE collection = ?1", params![name])?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Renames the tables of `name` to those of `new_name` in one transaction.
+/// Aliases of the collection follow it.
+pub fn rename_collection(conn: &Connection, name: &str, new_name: &str) -> Result<()> {
+    let config = get_collection_config(conn, name)?;
+    check_name_free(conn, new_name)?;
+    if config.rebuild.is_some() {
+        return Err(Error::InvalidArgument(format!(
+            "the index of collection `{}` is being rebuilt",
+            name
+        )));
+    }
+    let renamed = CollectionConfig {
+        index_generation: 0,
+        ..config.clone()
+    };
+
+    // vss0 has no xRename, so SQLite renames a vss0 table without telling
+    // it. The rename reloads the schema, after which vss0 reconnects under
+    // the new name and loads the index, trained or not, from the shadow
+    // tables named after it. They are renamed after the table, which
+    // still connects under the old name while the rename is prepared.
+    let mut vss_tables = vec![];
+    if !vector_columns(&config).is_empty() {
+        vss_tables.push((index_table(name, &config), index_table(new_name, &renamed)));
+    }
+    for vector in config.multivectors.keys() {
+        vss_tables.push((
+            multivector_tables(name, vector).0,
+            multivector_tables(new_name, vector).0,
+        ));
+    }
+    let mut renames = vec![];
+    for (from, to) in vss_tables {
+        for suffix in ["", "_index", "_data"] {
+            renames.push((format!("{}{}", from, suffix), format!("{}{}", to, suffix)));
         }
     }
+    renames.push((
+        format!("vss_{}_payload", name),
+        format!("vss_{}_payload", new_name),
+    ));
+    if config.stores_original_vectors() {
+        renames.push((
+            format!("vss_{}_vectors", name),
+            format!("vss_{}_vectors", new_name),
+        ));
+    }
+    if !config.sparse_vectors.is_empty() {
+        renames.push((
+            format!("vss_{}_sparse", name),
+            format!("vss_{}_sparse", new_name),
+        ));
+    }
+    for vector in config.multivectors.keys() {
+        renames.push((
+            multivector_tables(name, vector).1,
+            multivector_tables(new_name, vector).1,
+        ));
+    }
+    for key in geo_keys(&config) {
+        renames.push((geo_index_name(name, key), geo_index_name(new_name, key)));
+    }
+    let tx = conn.unchecked_transaction()?;
+    for (from, to) in &renames {
+        tx.execute_batch(&format!("ALTER TABLE {} RENAME TO {};", from, to))?;
+    }
 
-    let ids = map
-        .keys()
-        .map(|id| id.to_string())
-        .collect::<Vec<String>>()
+    // Indexes keep their names when their table is renamed. Recreate them
+    // under the new collection name.
+    let tables = renames
+        .iter()
+        .map(|(_, to)| format!("'{}'", to))
+        .collect::<Vec<_>>()
         .join(",");
+    let indexes = tx
+        .prepare(&format!(
+            "SELECT name, sql FROM sqlite_master WHERE type = 'index' AND sql IS NOT NULL AND tbl_name IN ({})",
+            tables
+        ))?
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let old_prefix = format!("vss_{}_", name);
+    for (index, sql) in indexes {
+        if let Some(suffix) = index.strip_prefix(&old_prefix) {
+            let new_index = format!("vss_{}_{}", new_name, suffix);
+            tx.execute_batch(&format!(
+                "DROP INDEX {}; {};",
+                index,
+                sql.replacen(&index, &new_index, 1)
+            ))?;
+        }
+    }
+    tx.execute("DELETE FROM collections WHERE name = ?1", params![name])?;
+    save_collection_config(&tx, new_name, &renamed)?;
+    tx.execute(
+        "UPDATE aliases SET collection = ?2 WHERE collection = ?1",
+        params![name, new_name],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
 
-    let payload_sql = format!(
-        r#"
-            SELECT * FROM vss_{}_payload WHERE rowid in ({});
-            "#,
-        name, ids
-    );
-    let mut payload_stmt = conn.prepare(payload_sql.as_str())?;
-    let payload_r = payload_stmt.query_map(params![], |row| {
-        let id: u64 = row.get(0)?;
-        let payload_str: String = row.get(1)?;
-        let payload: Option<serde_json::Map<String, serde_json::Value>> =
-            serde_json::from_str(&payload_str).unwrap_or_default();
-        Ok((id, payload))
-    })?;
+/// Copies the points of `name` matching `filter`, or all of them, into a
+/// new collection `new_name` with the same configuration and field indexes.
+pub fn clone_collection(
+    conn: &Connection,
+    name: &str,
+    new_name: &str,
+    filter: Option<&Filter>,
+) -> Result<()> {
+    let config = get_collection_config(conn, name)?;
+    check_name_free(conn, new_name)?;
+    let mut cloned = CollectionConfig {
+        index_generation: 0,
+        rebuild: None,
+        ..config.clone()
+    };
+    let filter = compile_filter(name, &config, filter)?.unwrap_or_else(|| SqlFilter {
+        sql: "1".to_string(),
+        params: vec![],
+    });
 
-    for v in payload_r {
-        if let Ok((id, payload)) = v {
-            if let Some(point) = map.get_mut(&id) {
-                point.payload = payload;
-            }
+    let tx = conn.unchecked_transaction()?;
+    tx.execute_batch(&data_tables(new_name, &cloned))?;
+    tx.execute_batch(&index_tables(new_name, &cloned))?;
+    tx.execute(
+        &format!(
+            "INSERT INTO vss_{}_payload (rowid, payload) SELECT p.rowid, p.payload FROM vss_{}_payload p WHERE {}",
+            new_name, name, filter.sql
+        ),
+        params_from_iter(filter.params),
+    )?;
+    let ids = format!("SELECT rowid FROM vss_{}_payload", new_name);
+    let mut sql = String::new();
+    if config.stores_original_vectors() {
+        sql.push_str(&format!(
+            "INSERT INTO vss_{}_vectors SELECT * FROM vss_{}_vectors WHERE rowid IN ({});",
+            new_name, name, ids
+        ));
+    }
+    if !config.sparse_vectors.is_empty() {
+        sql.push_str(&format!(
+            "INSERT INTO vss_{}_sparse SELECT * FROM vss_{}_sparse WHERE point_id IN ({});",
+            new_name, name, ids
+        ));
+    }
+    for vector in config.multivectors.keys() {
+        sql.push_str(&format!(
+            "INSERT INTO {} SELECT * FROM {} WHERE point_id IN ({});",
+            multivector_tables(new_name, vector).1,
+            multivector_tables(name, vector).1,
+            ids
+        ));
+    }
+    tx.execute_batch(&sql)?;
+    cloned.index_trained = fill_index(&tx, &config, name, new_name, Some(&ids))?;
+    for (key, schema) in &cloned.payload_schema {
+        if *schema == PayloadSchemaType::Geo {
+            create_geo_index(&tx, new_name, key)?;
+        } else {
+            create_payload_index(&tx, new_name, key)?;
         }
     }
+    save_collection_config(&tx, new_name, &cloned)?;
+    tx.commit()?;
+    Ok(())
+}
 
-    Ok(map.into_iter().map(|(_, v)| v).collect())
+/// Fills the vss0 tables of `target`, at index generation 0, with the
+/// vectors of `source`, restricted to the point ids selected by `ids`. A
+/// trained index is trained again on a sample of them. Returns whether the
+/// index of `target` is trained.
+fn fill_index(
+    conn: &Connection,
+    config: &CollectionConfig,
+    source: &str,
+    target: &str,
+    ids: Option<&str>,
+) -> Result<bool> {
+    let mut trained = config.index_trained;
+    let columns = vector_columns(config);
+    if !columns.is_empty() && !config.needs_training() {
+        let table = format!("vss_{}", target);
+        let vectors = match ids {
+            Some(ids) => format!(
+                "SELECT * FROM ({}) WHERE rowid IN ({})",
+                vector_source(config, source),
+                ids
+            ),
+            None => vector_source(config, source),
+        };
+        if config.stores_original_vectors() {
+            let trained_on = train_from(conn, &columns, &table, &vectors, DEFAULT_TRAINING_SAMPLE)?;
+            // Without points the next upsert or `train` trains the index.
+            trained = trained_on > 0;
+        }
+        if trained || !config.stores_original_vectors() {
+            index_from(conn, &columns, &table, &vectors)?;
+        }
+    }
+    for vector in config.multivectors.keys() {
+        let (_, tokens) = multivector_tables(source, vector);
+        let (index, _) = multivector_tables(target, vector);
+        let filter = ids
+            .map(|ids| format!(" WHERE point_id IN ({})", ids))
+            .unwrap_or_default();
+        conn.execute(
+            &format!(
+                "INSERT INTO {}(rowid, token) SELECT rowid, vector_from_raw(vector) FROM {}{}",
+                index, tokens, filter
+            ),
+            [],
+        )?;
+    }
+    Ok(trained)
 }
 
 #[test]
-fn test_points_search() {
+fn test_rename_and_clone() {
     use serde_json::json;
-    init();
-    let conn = rusqlite::Connection::open_in_memory().unwrap();
-    create_collections(&conn, "test_vss", 4).unwrap();
-    let mut points = Vec::<Point>::new();
-    {
-        points.push(Point {
-            id: 1,
-            vector: vec![0.05, 0.61, 0.76, 0.74],
-            payload: json!({"city": "Berlin"}).as_object().map(|m| m.to_owned()),
-        });
-        points.push(Point {
-            id: 2,
-            vector: vec![0.19, 0.81, 0.75, 0.11],
-            payload: json!({"city": "London"}).as_object().map(|m| m.to_owned()),
-        });
-        points.push(Point {
-            id: 3,
-            vector: vec![0.36, 0.55, 0.47, 0.94],
-            payload: json!({"city": "Moscow"}).as_object().map(|m| m.to_owned()),
-        });
-        points.push(Point {
-            id: 4,
-            vector: vec![0.18, 0.01, 0.85, 0.80],
-            payload: json!({"city": "New York"})
+
+    let conn = test_conn();
+    create_collections(&conn, "test_src", 2).unwrap();
+    let points = (1..=6)
+        .map(|i| Point {
+            id: i,
+            vector: vec![i as f32, 0.0].into(),
+            payload: json!({"parity": if i % 2 == 0 { "even" } else { "odd" }})
                 .as_object()
                 .map(|m| m.to_owned()),
-        });
-        points.push(Point {
-            id: 5,
-            vector: vec![0.24, 0.18, 0.22, 0.44],
-            payload: json!({"city": "Beijing"}).as_object().map(|m| m.to_owned()),
-        });
-        points.push(Point {
-            id: 6,
-            vector: vec![0.35, 0.08, 0.11, 0.44],
-            payload: json!({"city": "Mumbai"}).as_object().map(|m| m.to_owned()),
-        });
-    }
-    let r = add_point(&conn, "test_vss", &points).unwrap();
-    assert_eq!(r, vec![1, 2, 3, 4, 5, 6]);
+        })
+        .collect::<Vec<_>>();
+    add_point(&conn, "test_src", &points).unwrap();
+    create_field_index(&conn, "test_src", "parity", PayloadSchemaType::Keyword).unwrap();
 
-    let q = vec![0.2, 0.1, 0.9, 0.7];
-    let r = search_points(&conn, "test_vss", &q, 2).unwrap();
-    assert_eq!(r.len(), 2);
-    assert_eq!(r[0].id, 4);
-    assert_eq!(r[1].id, 1);
-}
+    let even: Filter =
+        serde_json::from_value(json!({"must": [{"key": "parity", "match": {"value": "even"}}]}))
+            .unwrap();
+    clone_collection(&conn, "test_src", "test_even", Some(&even)).unwrap();
+    assert_eq!(
+        get_collections_info(&conn, "test_even")
+            .unwrap()
+            .points_count,
+        3
+    );
+    let hits = search_points(&conn, "test_even", &[2.0, 0.0], 3).unwrap();
+    assert_eq!(hits.iter().map(|p| p.id).collect::<Vec<_>>(), vec![2, 4, 6]);
+    assert_eq!(
+        get_point(&conn, "test_even", 4).unwrap().unwrap().vector,
+        points[3].vector
+    );
+    assert!(matches!(
+        clone_collection(&conn, "test_src", "test_even", None),
+        Err(Error::CollectionExists(_))
+    ));
 
-pub fn delete_points(conn: &Connection, name: &str, ids: Vec<u64>) -> rusqlite::Result<()> {
-    let ids = ids
-        .iter()
-        .map(|id| id.to_string())
-        .collect::<Vec<String>>()
-        .join(",");
+    update_aliases(
+        &conn,
+        &[AliasOperation::CreateAlias {
+            collection_name: "test_src".to_string(),
+            alias_name: "test_live".to_string(),
+        }],
+    )
+    .unwrap();
+    rename_collection(&conn, "test_src", "test_dst").unwrap();
+    assert!(matches!(
+        get_collection_config(&conn, "test_src"),
+        Err(Error::CollectionNotFound(_))
+    ));
+    assert_eq!(resolve_alias(&conn, "test_live").unwrap(), "test_dst");
+    let info = get_collections_info(&conn, "test_dst").unwrap();
+    assert_eq!(info.points_count, 6);
+    assert_eq!(info.payload_schema["parity"].points, 6);
+    let hits = search_points(&conn, "test_dst", &[3.0, 0.0], 1).unwrap();
+    assert_eq!(hits[0].id, 3);
 
-    let sql = format!(
-        r#"
-        BEGIN;
-        DELETE FROM vss_{} WHERE rowid in ({});
-        DELETE FROM vss_{}_payload WHERE rowid in ({});
-        COMMIT;
-        "#,
-        name, ids, name, ids
-    );
-    conn.execute_batch(sql.as_str())
+    let tables: Vec<String> = conn
+        .prepare("SELECT name FROM sqlite_master WHERE name LIKE 'vss_test_src%'")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap();
+    assert!(tables.is_empty(), "{:?}", tables);
+    let index: u64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE name = 'vss_test_dst_payload_parity'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(index, 1);
 }
 
 #[test]
-fn test_points_delete() {
-    use serde_json::json;
+fn test_rename_reopen() {
+    use crate::{MultiVectorParams, NamedMultiVector, VectorsConfig};
+
     init();
-    let conn = rusqlite::Connection::open_in_memory().unwrap();
-    create_collections(&conn, "test_vss", 4).unwrap();
-    let mut points = Vec::<Point>::new();
-    {
-        points.push(Point {
-            id: 1,
-            vector: vec![0.05, 0.61, 0.76, 0.74],
-            payload: json!({"city": "Berlin"}).as_object().map(|m| m.to_owned()),
-        });
-        points.push(Point {
-            id: 2,
-            vector: vec![0.19, 0.81, 0.75, 0.11],
-            payload: json!({"city": "London"}).as_object().map(|m| m.to_owned()),
-        });
-        points.push(Point {
-            id: 3,
-            vector: vec![0.36, 0.55, 0.47, 0.94],
-            payload: json!({"city": "Moscow"}).as_object().map(|m| m.to_owned()),
-        });
-        points.push(Point {
-            id: 4,
-            vector: vec![0.18, 0.01, 0.85, 0.80],
-            payload: json!({"city": "New York"})
-                .as_object()
-                .map(|m| m.to_owned()),
-        });
-        points.push(Point {
-            id: 5,
-            vector: vec![0.24, 0.18, 0.22, 0.44],
-            payload: json!({"city": "Beijing"}).as_object().map(|m| m.to_owned()),
-        });
-        points.push(Point {
-            id: 6,
-            vector: vec![0.35, 0.08, 0.11, 0.44],
-            payload: json!({"city": "Mumbai"}).as_object().map(|m| m.to_owned()),
-        });
-    }
-    let r = add_point(&conn, "test_vss", &points).unwrap();
-    assert_eq!(r, vec![1, 2, 3, 4, 5, 6]);
+    let path = std::env::temp_dir().join(format!("test_rename_{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let conn = Connection::open(&path).unwrap();
+    init_schema(&conn).unwrap();
 
-    delete_points(&conn, "test_vss", vec![1, 2, 3, 4]).unwrap();
+    let mut config = CollectionConfig::new(8);
+    config.index_factory = Some("IVF4,PQ2x4".to_string());
+    create_collection_with_config(&conn, "test_ivf", &config).unwrap();
+    let points = (0..256)
+        .map(|i| Point {
+            id: i,
+            vector: (0..8)
+                .map(|d| ((i * 7 + d * 13) % 32) as f32 / 32.0)
+                .collect::<Vec<f32>>()
+                .into(),
+            payload: None,
+        })
+        .collect::<Vec<_>>();
+    add_point(&conn, "test_ivf", &points).unwrap();
+    train_index(&conn, "test_ivf", None).unwrap();
+    let query = points[5].vector.get(None).unwrap().to_vec();
+    let hits = search_points(&conn, "test_ivf", &query, 5).unwrap();
 
-    let r = get_points(&conn, "test_vss", vec![1, 2, 3, 4]).unwrap();
-    assert_eq!(r.len(), 0);
+    let mut config = CollectionConfig::with_vectors(VectorsConfig::Multi(BTreeMap::new()));
+    config.multivectors.insert(
+        "colbert".to_string(),
+        MultiVectorParams {
+            size: 2,
+            comparator: Default::default(),
+        },
+    );
+    create_collection_with_config(&conn, "test_mv", &config).unwrap();
+    let tokens = |id: u64, tokens: Vec<Vec<f32>>| Point {
+        id,
+        vector: VectorStruct::Named(BTreeMap::from([("colbert".to_string(), tokens.into())])),
+        payload: None,
+    };
+    add_point(
+        &conn,
+        "test_mv",
+        &[
+            tokens(1, vec![vec![1.0, 0.0], vec![0.0, 1.0]]),
+            tokens(2, vec![vec![0.0, 1.5]]),
+        ],
+    )
+    .unwrap();
+    let mut request = SearchRequest::new(vec![], 2);
+    request.vector = NamedVectorStruct::NamedMulti(NamedMultiVector {
+        name: "colbert".to_string(),
+        vector: vec![vec![1.0, 0.0], vec![0.0, 1.0]],
+    });
+
+    rename_collection(&conn, "test_ivf", "test_ivf2").unwrap();
+    rename_collection(&conn, "test_mv", "test_mv2").unwrap();
+    drop(conn);
+
+    // A new connection only sees what the renames left on disk.
+    let conn = Connection::open(&path).unwrap();
+    init_schema(&conn).unwrap();
+    assert!(
+        get_collection_config(&conn, "test_ivf2")
+            .unwrap()
+            .index_trained
+    );
+    assert_eq!(search_points(&conn, "test_ivf2", &query, 5).unwrap(), hits);
+    let r = search(&conn, "test_mv2", &request).unwrap();
+    assert_eq!(r.iter().map(|p| p.id).collect::<Vec<_>>(), vec![1, 2]);
+    assert_eq!(r[0].score, 2.0);
+
+    drop(conn);
+    std::fs::remove_file(&path).unwrap();
 }
 
-pub fn delete_collection(conn: &Connection, name: &str) -> rusqlite::Result<()> {
-    let sql = format!(
-        r#"
-        BEGIN;
-        DROP TABLE IF EXISTS vss_{};
-        DROP TABLE IF EXISTS vss_{}_payload;
-        COMMIT;
-        "#,
-        name, name
+#[test]
+fn test_aliases() {
+    let conn = test_conn();
+    create_collections(&conn, "test_blue", 2).unwrap();
+    create_collections(&conn, "test_green", 2).unwrap();
+    let create = |alias: &str, collection: &str| AliasOperation::CreateAlias {
+        collection_name: collection.to_string(),
+        alias_name: alias.to_string(),
+    };
+
+    update_aliases(&conn, &[create("test_live", "test_blue")]).unwrap();
+    assert_eq!(resolve_alias(&conn, "test_live").unwrap(), "test_blue");
+    assert_eq!(resolve_alias(&conn, "test_green").unwrap(), "test_green");
+
+    // Switching over is one transaction, a failing action undoes the others.
+    assert!(matches!(
+        update_aliases(
+            &conn,
+            &[
+                AliasOperation::DeleteAlias {
+                    alias_name: "test_live".to_string()
+                },
+                create("test_live", "test_missing"),
+            ]
+        ),
+        Err(Error::CollectionNotFound(_))
+    ));
+    assert_eq!(resolve_alias(&conn, "test_live").unwrap(), "test_blue");
+    update_aliases(
+        &conn,
+        &[
+            AliasOperation::DeleteAlias {
+                alias_name: "test_live".to_string(),
+            },
+            create("test_live", "test_green"),
+        ],
+    )
+    .unwrap();
+    assert_eq!(resolve_alias(&conn, "test_live").unwrap(), "test_green");
+
+    update_aliases(
+        &conn,
+        &[AliasOperation::RenameAlias {
+            old_alias_name: "test_live".to_string(),
+            new_alias_name: "test_prod".to_string(),
+        }],
+    )
+    .unwrap();
+    assert_eq!(
+        list_aliases(&conn).unwrap(),
+        vec![AliasDescription {
+            alias_name: "test_prod".to_string(),
+            collection_name: "test_green".to_string(),
+        }]
     );
-    conn.execute_batch(sql.as_str())
+    assert!(matches!(
+        update_aliases(
+            &conn,
+            &[AliasOperation::DeleteAlias {
+                alias_name: "test_live".to_string()
+            }]
+        ),
+        Err(Error::AliasNotFound(_))
+    ));
+
+    // Collections and aliases share one namespace.
+    assert!(matches!(
+        update_aliases(&conn, &[create("test_blue", "test_green")]),
+        Err(Error::CollectionExists(_))
+    ));
+    assert!(matches!(
+        create_collections(&conn, "test_prod", 2),
+        Err(Error::CollectionExists(_))
+    ));
+
+    delete_collection(&conn, "test_green").unwrap();
+    assert!(list_aliases(&conn).unwrap().is_empty());
+}
+
+#[test]
+fn test_collection_errors() {
+    let conn = test_conn();
+    create_collections(&conn, "test_vss", 4).unwrap();
+    assert!(matches!(
+        create_collections(&conn, "test_vss", 4),
+        Err(Error::CollectionExists(_))
+    ));
+    create_collections(&conn, "other_data", 4).unwrap();
+    for name in ["test_vss_g2", "test_vss_payload", "test_vss_mv_x", "other"] {
+        assert!(matches!(
+            create_collections(&conn, name, 4),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+    create_collections(&conn, "test_vss_v2", 4).unwrap();
+    create_collections(&conn, "test_vss_gold", 4).unwrap();
+    assert!(matches!(
+        search_points(&conn, "missing", &[0.0; 4], 1),
+        Err(Error::CollectionNotFound(_))
+    ));
+    assert!(matches!(
+        search_points(&conn, "test_vss", &[0.0; 3], 1),
+        Err(Error::DimensionMismatch {
+            expected: 4,
+            got: 3
+        })
+    ));
+    delete_collection(&conn, "test_vss").unwrap();
+    assert!(matches!(
+        get_collections_info(&conn, "test_vss"),
+        Err(Error::CollectionNotFound(_))
+    ));
 }
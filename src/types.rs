@@ -0,0 +1,880 @@
+use std::collections::BTreeMap;
+
+pub type Payload = serde_json::Map<String, serde_json::Value>;
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Point {
+    pub id: u64,
+    pub vector: VectorStruct,
+    pub payload: Option<Payload>,
+}
+
+/// A write to the points of a collection. Every operation except `upsert`
+/// fails with [`Error::PointNotFound`](crate::Error) on a missing point.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PointOperation {
+    Upsert {
+        points: Vec<Point>,
+    },
+    Delete {
+        points: Vec<u64>,
+    },
+    /// Merges `payload` into the payload of each point.
+    SetPayload {
+        payload: Payload,
+        points: Vec<u64>,
+    },
+    /// Removes the top-level `keys` from the payload of each point.
+    DeletePayload {
+        keys: Vec<String>,
+        points: Vec<u64>,
+    },
+    ClearPayload {
+        points: Vec<u64>,
+    },
+    /// Replaces the given vectors of each point, keeping its other vectors
+    /// and its payload.
+    UpdateVectors {
+        points: Vec<PointVectors>,
+    },
+}
+
+impl PointOperation {
+    /// Ids of the points whose vectors the operation writes or deletes.
+    pub fn vector_ids(&self) -> Vec<u64> {
+        match self {
+            PointOperation::Upsert { points } => points.iter().map(|p| p.id).collect(),
+            PointOperation::Delete { points } => points.clone(),
+            PointOperation::UpdateVectors { points } => points.iter().map(|p| p.id).collect(),
+            PointOperation::SetPayload { .. }
+            | PointOperation::DeletePayload { .. }
+            | PointOperation::ClearPayload { .. } => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct PointVectors {
+    pub id: u64,
+    pub vector: VectorStruct,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ScoredPoint {
+    pub id: u64,
+    pub vector: VectorStruct,
+    pub payload: Option<Payload>,
+    pub score: f32,
+}
+
+/// The vectors of a point: a plain list for collections with a single
+/// unnamed vector, or one vector per name, e.g. `{"text": [...], "image": [...]}`.
+/// In the named form the unnamed vector is called `""`.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+pub enum VectorStruct {
+    Single(Vec<f32>),
+    Named(BTreeMap<String, Vector>),
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+pub enum Vector {
+    Dense(Vec<f32>),
+    Sparse(SparseVector),
+    /// One vector per token of a late interaction model such as ColBERT.
+    Multi(Vec<Vec<f32>>),
+}
+
+impl From<Vec<f32>> for Vector {
+    fn from(vector: Vec<f32>) -> Self {
+        Vector::Dense(vector)
+    }
+}
+
+impl From<SparseVector> for Vector {
+    fn from(vector: SparseVector) -> Self {
+        Vector::Sparse(vector)
+    }
+}
+
+impl From<Vec<Vec<f32>>> for Vector {
+    fn from(vectors: Vec<Vec<f32>>) -> Self {
+        Vector::Multi(vectors)
+    }
+}
+
+/// Non-zero dimensions of a sparse vector, e.g. SPLADE or BM25 weights.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SparseVector {
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
+}
+
+impl Default for VectorStruct {
+    fn default() -> Self {
+        VectorStruct::Single(vec![])
+    }
+}
+
+impl From<Vec<f32>> for VectorStruct {
+    fn from(vector: Vec<f32>) -> Self {
+        VectorStruct::Single(vector)
+    }
+}
+
+impl VectorStruct {
+    /// The dense vector called `name`, or the unnamed one for `None`.
+    pub fn get(&self, name: Option<&str>) -> Option<&[f32]> {
+        match (self, name) {
+            (VectorStruct::Single(vector), None) => Some(vector),
+            (VectorStruct::Single(_), Some(_)) => None,
+            (VectorStruct::Named(vectors), name) => match vectors.get(name.unwrap_or_default()) {
+                Some(Vector::Dense(vector)) => Some(vector),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn get_sparse(&self, name: &str) -> Option<&SparseVector> {
+        match self {
+            VectorStruct::Named(vectors) => match vectors.get(name) {
+                Some(Vector::Sparse(vector)) => Some(vector),
+                _ => None,
+            },
+            VectorStruct::Single(_) => None,
+        }
+    }
+
+    pub fn get_multi(&self, name: &str) -> Option<&[Vec<f32>]> {
+        match self {
+            VectorStruct::Named(vectors) => match vectors.get(name) {
+                Some(Vector::Multi(vectors)) => Some(vectors),
+                _ => None,
+            },
+            VectorStruct::Single(_) => None,
+        }
+    }
+
+    /// Adds a named vector, switching a plain list to the named form.
+    pub fn insert(&mut self, name: String, vector: Vector) {
+        if let VectorStruct::Single(single) = self {
+            let single = std::mem::take(single);
+            *self = VectorStruct::Named(BTreeMap::from([(String::new(), single.into())]));
+        }
+        if let VectorStruct::Named(vectors) = self {
+            vectors.insert(name, vector);
+        }
+    }
+}
+
+/// The query vector of a search: a plain list, or
+/// `{"name": "image", "vector": [...]}` on collections with named vectors.
+/// Multivectors are queried by name with a list of token vectors.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+pub enum NamedVectorStruct {
+    Default(Vec<f32>),
+    Named(NamedVector),
+    NamedMulti(NamedMultiVector),
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct NamedVector {
+    pub name: String,
+    pub vector: Vec<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct NamedMultiVector {
+    pub name: String,
+    pub vector: Vec<Vec<f32>>,
+}
+
+impl From<Vec<f32>> for NamedVectorStruct {
+    fn from(vector: Vec<f32>) -> Self {
+        NamedVectorStruct::Default(vector)
+    }
+}
+
+impl NamedVectorStruct {
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            NamedVectorStruct::Default(_) => None,
+            NamedVectorStruct::Named(named) => Some(&named.name),
+            NamedVectorStruct::NamedMulti(named) => Some(&named.name),
+        }
+    }
+
+    /// The dense query vector, empty for a multivector query.
+    pub fn vector(&self) -> &[f32] {
+        match self {
+            NamedVectorStruct::Default(vector) => vector,
+            NamedVectorStruct::Named(named) => &named.vector,
+            NamedVectorStruct::NamedMulti(_) => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct CollectionInfo {
+    pub points_count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quantization: Option<QuantizationInfo>,
+    /// Indexed payload fields.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub payload_schema: BTreeMap<String, PayloadIndexInfo>,
+    /// Set while the dense index is being rebuilt.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rebuild: Option<RebuildInfo>,
+}
+
+/// Progress of a dense index rebuild, persisted in the collection config.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct IndexRebuild {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_factory: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantization_config: Option<QuantizationConfig>,
+    /// Whether the new index has been trained, for indexes that need it.
+    pub trained: bool,
+    /// Points are copied in id order, up to and including this id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copied_up_to: Option<u64>,
+    pub points_copied: u64,
+    /// Vectors to train on, for a rebuild started by `train`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_size: Option<usize>,
+}
+
+/// `PATCH /collections/:name`: the dense index settings to rebuild with.
+/// Omitted settings are reset, so `{}` rebuilds a flat index.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct CollectionUpdate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_factory: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantization_config: Option<QuantizationConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct RebuildInfo {
+    /// The FAISS factory of the index being built.
+    pub index_factory: String,
+    pub points_copied: u64,
+    pub points_count: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    /// Trains a custom index factory and indexes every point.
+    TrainIndex,
+    /// Rebuilds the dense index with the settings of a collection update.
+    RebuildIndex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    /// Whether the job still has work to do.
+    pub fn is_active(&self) -> bool {
+        matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+/// A background job, as returned by `GET /jobs/:id`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct JobInfo {
+    pub id: u64,
+    pub kind: JobKind,
+    pub collection_name: String,
+    pub status: JobStatus,
+    /// Points processed so far, out of `total`.
+    pub done: u64,
+    pub total: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Unix timestamps in seconds.
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct PayloadIndexInfo {
+    pub data_type: PayloadSchemaType,
+    /// Points that have a value for the field.
+    pub points: u64,
+}
+
+/// Qdrant's `field_schema` of a payload index. Every type but `geo` is
+/// indexed with the same expression index on the JSON value, which serves
+/// `match`, `range` and scroll ordering. `text` asks for a full-text index,
+/// which is not supported, and is rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadSchemaType {
+    Keyword,
+    Integer,
+    Float,
+    Bool,
+    Datetime,
+    Text,
+    /// `{"lat": .., "lon": ..}` objects, indexed in an R*Tree.
+    Geo,
+}
+
+/// Storage footprint of a quantized collection compared to plain `f32`.
+///
+/// The originals are kept next to the index for rescoring, so the
+/// collection stores `index_bytes + original_vectors_bytes` where a `Flat`
+/// index would store `original_vectors_bytes`. What shrinks is the index
+/// that searches scan.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct QuantizationInfo {
+    #[serde(rename = "type")]
+    pub kind: ScalarType,
+    pub original_vector_bytes: usize,
+    pub quantized_vector_bytes: usize,
+    pub compression_ratio: f32,
+    /// Quantized vectors of the current points in the index.
+    pub index_bytes: u64,
+    /// `f32` vectors of the current points in `vss_{name}_vectors`.
+    pub original_vectors_bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct VectorParams {
+    pub size: usize,
+}
+
+/// Sparse vectors have no parameters yet, the empty object keeps the
+/// config shape of Qdrant's `sparse_vectors`.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct SparseVectorParams {}
+
+/// A vector per token, compared with `max_sim`: every query token is
+/// matched with its most similar document token by dot product, and the
+/// similarities are summed.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct MultiVectorParams {
+    pub size: usize,
+    #[serde(default)]
+    pub comparator: MultiVectorComparator,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MultiVectorComparator {
+    #[default]
+    MaxSim,
+}
+
+/// Qdrant's `vectors` config: `{"size": 4}` for a single unnamed vector, or
+/// `{"text": {"size": 1024}, "image": {"size": 512}}` for named vectors.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+pub enum VectorsConfig {
+    Single(VectorParams),
+    Multi(BTreeMap<String, VectorParams>),
+}
+
+impl VectorsConfig {
+    /// `(name, size)` of every vector, `None` naming the unnamed one.
+    pub fn params(&self) -> Vec<(Option<&str>, usize)> {
+        match self {
+            VectorsConfig::Single(params) => vec![(None, params.size)],
+            VectorsConfig::Multi(params) => params
+                .iter()
+                .map(|(name, params)| (Some(name.as_str()), params.size))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScalarType {
+    Int8,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ScalarQuantization {
+    #[serde(rename = "type")]
+    pub kind: ScalarType,
+}
+
+/// Qdrant's `quantization_config`, e.g. `{"scalar": {"type": "int8"}}`.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuantizationConfig {
+    Scalar(ScalarQuantization),
+}
+
+/// Persisted per collection in the `collections` table.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct CollectionConfig {
+    pub vectors: VectorsConfig,
+    /// Custom FAISS index factory, e.g. `IVF1024,PQ64`. Such indexes are
+    /// trained explicitly once enough points have been upserted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_factory: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantization_config: Option<QuantizationConfig>,
+    /// Sparse vectors by name, kept in an inverted index outside of vss0.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sparse_vectors: BTreeMap<String, SparseVectorParams>,
+    /// Multivectors by name, each token in its own flat vss0 table.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub multivectors: BTreeMap<String, MultiVectorParams>,
+    /// Payload fields with an index, by dotted key.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub payload_schema: BTreeMap<String, PayloadSchemaType>,
+    /// Set once a factory that needs training has been trained.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub index_trained: bool,
+    /// Bumped by each index rebuild. The rebuilt index is filled next to
+    /// the one still serving searches, so the dense index of generation
+    /// `n > 0` lives in `vss_{name}_g{n}`. Renaming a collection resets it.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub index_generation: u64,
+    /// A rebuild of the dense index started by a collection update.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rebuild: Option<IndexRebuild>,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+impl CollectionConfig {
+    pub fn new(size: usize) -> Self {
+        Self::with_vectors(VectorsConfig::Single(VectorParams { size }))
+    }
+
+    pub fn with_vectors(vectors: VectorsConfig) -> Self {
+        Self {
+            vectors,
+            index_factory: None,
+            quantization_config: None,
+            sparse_vectors: BTreeMap::new(),
+            multivectors: BTreeMap::new(),
+            payload_schema: BTreeMap::new(),
+            index_trained: false,
+            index_generation: 0,
+            rebuild: None,
+        }
+    }
+
+    /// The configuration `rebuild` switches to once it is done.
+    pub fn rebuilt(&self, rebuild: &IndexRebuild) -> CollectionConfig {
+        CollectionConfig {
+            index_factory: rebuild.index_factory.clone(),
+            quantization_config: rebuild.quantization_config.clone(),
+            index_trained: rebuild.trained,
+            index_generation: self.index_generation + 1,
+            rebuild: None,
+            ..self.clone()
+        }
+    }
+
+    /// The FAISS index factory passed to vss0. vss0 adds vectors with ids,
+    /// so indexes without native id support are wrapped in `IDMap2`.
+    pub fn index_factory(&self) -> String {
+        match (&self.index_factory, &self.quantization_config) {
+            (Some(factory), _) if factory.contains("IDMap") => factory.clone(),
+            (Some(factory), _) => format!("{},IDMap2", factory),
+            (
+                None,
+                Some(QuantizationConfig::Scalar(ScalarQuantization {
+                    kind: ScalarType::Int8,
+                })),
+            ) => "SQ8,IDMap2".to_string(),
+            (None, None) => "Flat,IDMap2".to_string(),
+        }
+    }
+
+    pub fn is_quantized(&self) -> bool {
+        self.quantization_config.is_some()
+    }
+
+    /// Everything but a flat index has to be trained before vectors can be
+    /// added, and only returns approximations of the stored vectors, so the
+    /// originals are kept in `vss_{name}_vectors`.
+    pub fn stores_original_vectors(&self) -> bool {
+        !self.index_factory().starts_with("Flat")
+    }
+
+    pub fn needs_training(&self) -> bool {
+        self.stores_original_vectors() && !self.index_trained
+    }
+
+    /// Scalar quantization only learns value ranges, so the first upserted
+    /// batch is good enough. Custom factories wait for an explicit train.
+    pub fn trains_on_first_batch(&self) -> bool {
+        self.index_factory.is_none() && self.is_quantized()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct QuantizationSearchParams {
+    /// Re-rank candidates against the original `f32` vectors. Defaults to true.
+    pub rescore: Option<bool>,
+    /// Fetch `limit * oversampling` candidates before rescoring.
+    pub oversampling: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct SearchParams {
+    pub quantization: Option<QuantizationSearchParams>,
+    /// Scan every stored vector instead of querying the index.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub exact: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct SearchRequest {
+    pub vector: NamedVectorStruct,
+    pub limit: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<Filter>,
+    #[serde(default)]
+    pub params: SearchParams,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mmr: Option<Mmr>,
+}
+
+impl SearchRequest {
+    pub fn new(vector: Vec<f32>, limit: usize) -> Self {
+        Self {
+            vector: vector.into(),
+            limit,
+            filter: None,
+            params: SearchParams::default(),
+            mmr: None,
+        }
+    }
+}
+
+/// Maximal marginal relevance: re-ranks the nearest `candidates_limit`
+/// points, trading closeness to the query for distance to the points
+/// already picked.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Mmr {
+    /// From 0, plain nearest neighbors, to 1, as spread out as possible.
+    /// Defaults to 0.5.
+    pub diversity: Option<f32>,
+    /// Candidates to re-rank, at least `limit`. Defaults to 100.
+    pub candidates_limit: Option<usize>,
+}
+
+/// Qdrant's payload filter: every `must` condition, at least one `should`
+/// condition if any are given, and none of the `must_not` conditions.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub must: Vec<Condition>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub should: Vec<Condition>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub must_not: Vec<Condition>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+pub enum Condition {
+    HasId(HasIdCondition),
+    Field(FieldCondition),
+    Filter(Filter),
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HasIdCondition {
+    pub has_id: Vec<u64>,
+}
+
+/// A condition on the payload value at `key`, a dotted path such as
+/// `address.city`. Exactly one of `match`, `range` and the geo conditions
+/// must be set.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldCondition {
+    pub key: String,
+    #[serde(rename = "match", default, skip_serializing_if = "Option::is_none")]
+    pub r#match: Option<Match>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<Range>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geo_radius: Option<GeoRadius>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geo_bounding_box: Option<GeoBoundingBox>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geo_polygon: Option<GeoPolygon>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+pub enum Match {
+    Value { value: serde_json::Value },
+    Any { any: Vec<serde_json::Value> },
+    Except { except: Vec<serde_json::Value> },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Range {
+    pub gt: Option<f64>,
+    pub gte: Option<f64>,
+    pub lt: Option<f64>,
+    pub lte: Option<f64>,
+}
+
+/// A payload location, `{"lat": 52.52, "lon": 13.40}`, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// Locations within `radius` meters of `center`.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeoRadius {
+    pub center: GeoPoint,
+    pub radius: f64,
+}
+
+/// Locations inside the box. A box whose `top_left` is east of its
+/// `bottom_right` crosses the antimeridian.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeoBoundingBox {
+    pub top_left: GeoPoint,
+    pub bottom_right: GeoPoint,
+}
+
+/// Locations inside `exterior` and outside all `interiors`.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeoPolygon {
+    pub exterior: GeoLineString,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub interiors: Vec<GeoLineString>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeoLineString {
+    pub points: Vec<GeoPoint>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct TrainRequest {
+    /// Number of stored vectors to train on, sampled at random.
+    pub sample_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct TrainReport {
+    pub index_factory: String,
+    pub trained_on: usize,
+    pub points_indexed: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct EvaluateRequest {
+    /// Number of stored points used as queries.
+    #[serde(default = "EvaluateRequest::default_sample_size")]
+    pub sample_size: usize,
+    /// The `k` of recall@k.
+    #[serde(default = "EvaluateRequest::default_limit")]
+    pub limit: usize,
+    /// Vector name to evaluate on collections with named vectors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub using: Option<String>,
+    #[serde(default)]
+    pub params: SearchParams,
+}
+
+impl EvaluateRequest {
+    fn default_sample_size() -> usize {
+        100
+    }
+
+    fn default_limit() -> usize {
+        10
+    }
+}
+
+/// Approximate search compared against exact search on the same queries.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct EvaluationReport {
+    pub index_factory: String,
+    pub sample_size: usize,
+    pub limit: usize,
+    /// Mean fraction of the exact top-k found by the approximate search.
+    pub recall: f64,
+    pub min_recall: f64,
+    pub approximate_ms: f64,
+    pub exact_ms: f64,
+}
+
+/// Dot-product search over a sparse vector,
+/// e.g. `{"vector": {"name": "text", "vector": {"indices": [1], "values": [0.5]}}}`.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct SparseSearchRequest {
+    pub vector: NamedSparseVector,
+    pub limit: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<Filter>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct NamedSparseVector {
+    pub name: String,
+    pub vector: SparseVector,
+}
+
+/// Runs every dense and sparse search and merges their rankings.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct FusionRequest {
+    #[serde(default)]
+    pub dense: Vec<SearchRequest>,
+    #[serde(default)]
+    pub sparse: Vec<SparseSearchRequest>,
+    pub limit: usize,
+    #[serde(default)]
+    pub fusion: Fusion,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fusion {
+    /// Reciprocal rank fusion: each point scores `sum(1 / (60 + rank))`.
+    #[default]
+    Rrf,
+}
+
+/// A search returning the best `limit` groups of points sharing the value
+/// of the payload key `group_by`, with up to `group_size` hits each.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct SearchGroupsRequest {
+    pub vector: NamedVectorStruct,
+    pub group_by: String,
+    pub group_size: usize,
+    pub limit: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<Filter>,
+    #[serde(default)]
+    pub params: SearchParams,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct PointGroup {
+    /// The `group_by` value, a string or an integer.
+    pub id: serde_json::Value,
+    pub hits: Vec<ScoredPoint>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct GroupsResult {
+    pub groups: Vec<PointGroup>,
+}
+
+/// Pages through the points of a collection, by id or by a payload key.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ScrollRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<Filter>,
+    #[serde(default = "default_scroll_limit")]
+    pub limit: usize,
+    /// First point id of the page, the previous page's `next_page_offset`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order_by: Option<OrderBy>,
+}
+
+fn default_scroll_limit() -> usize {
+    10
+}
+
+impl Default for ScrollRequest {
+    fn default() -> Self {
+        Self {
+            filter: None,
+            limit: default_scroll_limit(),
+            offset: None,
+            order_by: None,
+        }
+    }
+}
+
+/// Orders a scroll by the value of a payload key. Points without it are
+/// skipped. Pages continue with `start_from` set to the last value seen.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct OrderBy {
+    pub key: String,
+    #[serde(default)]
+    pub direction: Direction,
+    /// Inclusive bound to start at, a number or a string such as an
+    /// RFC 3339 timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_from: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ScrollResult {
+    pub points: Vec<Point>,
+    /// Offset of the next page, unset on the last page and when ordering
+    /// by a payload key.
+    pub next_page_offset: Option<u64>,
+}
+
+/// One step of `POST /collections/aliases`. A request's actions are applied
+/// in a single transaction, so an alias can be switched to another
+/// collection without a moment where it is missing.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AliasOperation {
+    /// Points `alias_name` at `collection_name`, replacing its target if the
+    /// alias exists.
+    CreateAlias {
+        collection_name: String,
+        alias_name: String,
+    },
+    DeleteAlias {
+        alias_name: String,
+    },
+    RenameAlias {
+        old_alias_name: String,
+        new_alias_name: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct AliasDescription {
+    pub alias_name: String,
+    pub collection_name: String,
+}
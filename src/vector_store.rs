@@ -0,0 +1,315 @@
+use std::{path::Path, sync::Once};
+
+use rusqlite::Connection;
+
+use crate::{
+    jobs, store, AliasDescription, AliasOperation, CollectionConfig, CollectionInfo,
+    CollectionUpdate, EvaluateRequest, EvaluationReport, Filter, FusionRequest, JobInfo, JobKind,
+    PayloadSchemaType, Point, PointGroup, PointOperation, Result, ScoredPoint, ScrollRequest,
+    ScrollResult, SearchGroupsRequest, SearchRequest, SparseSearchRequest, TrainReport,
+};
+
+static INIT: Once = Once::new();
+
+/// Owns the SQLite connection. Collections are accessed through
+/// [`VectorStore::collection`].
+pub struct VectorStore {
+    conn: Connection,
+}
+
+impl VectorStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        INIT.call_once(store::init);
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        INIT.call_once(store::init);
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self> {
+        store::init_schema(&conn)?;
+        Ok(Self { conn })
+    }
+
+    /// Gives access to the underlying connection, e.g. to set pragmas.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    pub fn create_collection(&self, name: &str, size: usize) -> Result<Collection<'_>> {
+        store::create_collections(&self.conn, name, size)?;
+        Ok(self.handle(name))
+    }
+
+    pub fn create_collection_with_config(
+        &self,
+        name: &str,
+        config: &CollectionConfig,
+    ) -> Result<Collection<'_>> {
+        store::create_collection_with_config(&self.conn, name, config)?;
+        Ok(self.handle(name))
+    }
+
+    pub fn delete_collection(&self, name: &str) -> Result<()> {
+        store::delete_collection(&self.conn, name)
+    }
+
+    /// Renames collection `name`; its aliases point at `new_name` after.
+    pub fn rename_collection(&self, name: &str, new_name: &str) -> Result<Collection<'_>> {
+        store::rename_collection(&self.conn, name, new_name)?;
+        Ok(self.handle(new_name))
+    }
+
+    /// Copies `name`, or the collection it is an alias of, into a new
+    /// collection. With a `filter` only the matching points are copied.
+    pub fn clone_collection(
+        &self,
+        name: &str,
+        new_name: &str,
+        filter: Option<&Filter>,
+    ) -> Result<Collection<'_>> {
+        let source = self.collection(name)?;
+        store::clone_collection(&self.conn, source.name(), new_name, filter)?;
+        Ok(self.handle(new_name))
+    }
+
+    /// Returns a handle to `name`, or to the collection it is an alias of.
+    /// Existence is checked by each operation, which fails with
+    /// [`Error::CollectionNotFound`](crate::Error).
+    pub fn collection(&self, name: &str) -> Result<Collection<'_>> {
+        let name = store::resolve_alias(&self.conn, name)?;
+        Ok(self.handle(&name))
+    }
+
+    /// A handle to collection `name` itself, which is never an alias.
+    fn handle(&self, name: &str) -> Collection<'_> {
+        Collection {
+            conn: &self.conn,
+            name: name.to_string(),
+        }
+    }
+
+    /// Creates, deletes and renames aliases in one transaction.
+    pub fn update_aliases(&self, actions: &[AliasOperation]) -> Result<()> {
+        store::update_aliases(&self.conn, actions)
+    }
+
+    pub fn aliases(&self) -> Result<Vec<AliasDescription>> {
+        store::list_aliases(&self.conn)
+    }
+
+    /// Applies `(collection, operations)` writes in order, committing
+    /// consecutive writes together where possible. Every write succeeds or
+    /// fails on its own, as a whole; returns the result of each.
+    pub fn apply_writes(&self, writes: &[(&str, &[PointOperation])]) -> Vec<Result<()>> {
+        let names = writes
+            .iter()
+            .map(|(name, _)| store::resolve_alias(&self.conn, name))
+            .collect::<Vec<_>>();
+        // A write whose collection can't be looked up fails on its own.
+        let resolved = names
+            .iter()
+            .zip(writes)
+            .filter_map(|(name, (_, operations))| Some((name.as_ref().ok()?.as_str(), *operations)))
+            .collect::<Vec<_>>();
+        let mut results = store::apply_writes(&self.conn, &resolved).into_iter();
+        names
+            .into_iter()
+            .map(|name| match name {
+                Ok(_) => results.next().expect("one result per resolved write"),
+                Err(e) => Err(e),
+            })
+            .collect()
+    }
+
+    /// Every background job, newest first.
+    pub fn jobs(&self) -> Result<Vec<JobInfo>> {
+        jobs::list(&self.conn)
+    }
+
+    pub fn job(&self, id: u64) -> Result<JobInfo> {
+        jobs::get(&self.conn, id)
+    }
+
+    /// Ids of the jobs to drive with [`run_job_step`](Self::run_job_step),
+    /// including those a previous process left unfinished.
+    pub fn active_jobs(&self) -> Result<Vec<u64>> {
+        jobs::active(&self.conn)
+    }
+
+    /// Runs one short transaction of job `id`, processing at most
+    /// `batch_size` points. The job is done once its status is no longer
+    /// active.
+    pub fn run_job_step(&self, id: u64, batch_size: usize) -> Result<JobInfo> {
+        jobs::step(&self.conn, id, batch_size)
+    }
+
+    /// Stops job `id` and undoes its work.
+    pub fn cancel_job(&self, id: u64) -> Result<JobInfo> {
+        jobs::cancel(&self.conn, id)
+    }
+
+    /// Confirms the vss extensions are loaded and the database is writable.
+    pub fn check_ready(&self) -> Result<()> {
+        store::check_ready(&self.conn)
+    }
+
+    pub fn checkpoint(&self) -> Result<()> {
+        store::checkpoint(&self.conn)
+    }
+
+    pub fn close(self) -> Result<()> {
+        self.conn.close().map_err(|(_, e)| e.into())
+    }
+}
+
+pub struct Collection<'a> {
+    conn: &'a Connection,
+    name: String,
+}
+
+impl<'a> Collection<'a> {
+    /// The collection's own name, also when the handle was opened by alias.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn config(&self) -> Result<CollectionConfig> {
+        store::get_collection_config(self.conn, &self.name)
+    }
+
+    pub fn info(&self) -> Result<CollectionInfo> {
+        store::get_collections_info(self.conn, &self.name)
+    }
+
+    /// Queues a job rebuilding the dense index with new settings. The
+    /// current index serves searches until the job has copied every point
+    /// and swapped the new one in.
+    pub fn update(&self, update: &CollectionUpdate) -> Result<JobInfo> {
+        store::update_collection(self.conn, &self.name, update)?;
+        jobs::create(self.conn, JobKind::RebuildIndex, &self.name)
+    }
+
+    /// Indexes a payload field, speeding up filters and ordering on it.
+    pub fn create_field_index(&self, field_name: &str, schema: PayloadSchemaType) -> Result<()> {
+        store::create_field_index(self.conn, &self.name, field_name, schema)
+    }
+
+    pub fn delete_field_index(&self, field_name: &str) -> Result<()> {
+        store::delete_field_index(self.conn, &self.name, field_name)
+    }
+
+    /// Inserts or replaces `points`, returning the ids written.
+    pub fn upsert(&self, points: &[Point]) -> Result<Vec<u64>> {
+        store::add_point(self.conn, &self.name, points)
+    }
+
+    pub fn get(&self, id: u64) -> Result<Option<Point>> {
+        store::get_point(self.conn, &self.name, id)
+    }
+
+    pub fn get_many(&self, ids: Vec<u64>) -> Result<Vec<Point>> {
+        store::get_points(self.conn, &self.name, ids)
+    }
+
+    /// A page of points, by id or ordered by a payload key.
+    pub fn scroll(&self, request: &ScrollRequest) -> Result<ScrollResult> {
+        store::scroll(self.conn, &self.name, request)
+    }
+
+    pub fn search(&self, vector: &[f32], limit: usize) -> Result<Vec<ScoredPoint>> {
+        store::search_points(self.conn, &self.name, vector, limit)
+    }
+
+    pub fn search_with(&self, request: &SearchRequest) -> Result<Vec<ScoredPoint>> {
+        store::search(self.conn, &self.name, request)
+    }
+
+    /// The best `limit` groups of points sharing a payload value.
+    pub fn search_groups(&self, request: &SearchGroupsRequest) -> Result<Vec<PointGroup>> {
+        store::search_groups(self.conn, &self.name, request)
+    }
+
+    /// Trains a custom index factory on up to `sample_size` stored vectors
+    /// and indexes every point. Searches fail until this has run.
+    pub fn train(&self, sample_size: Option<usize>) -> Result<TrainReport> {
+        store::train_index(self.conn, &self.name, sample_size)
+    }
+
+    /// Like [`train`](Self::train), as a job that indexes the points in
+    /// batches.
+    pub fn start_training(&self, sample_size: Option<usize>) -> Result<JobInfo> {
+        store::start_training(self.conn, &self.name, sample_size)?;
+        jobs::create(self.conn, JobKind::TrainIndex, &self.name)
+    }
+
+    /// Reports recall@k of the index against exact search.
+    pub fn evaluate(&self, request: &EvaluateRequest) -> Result<EvaluationReport> {
+        store::evaluate(self.conn, &self.name, request)
+    }
+
+    /// Dot-product search over a sparse vector; higher scores are better.
+    pub fn search_sparse(&self, request: &SparseSearchRequest) -> Result<Vec<ScoredPoint>> {
+        store::search_sparse(self.conn, &self.name, request)
+    }
+
+    /// Merges the rankings of several dense and sparse searches.
+    pub fn search_fusion(&self, request: &FusionRequest) -> Result<Vec<ScoredPoint>> {
+        store::fuse(self.conn, &self.name, request)
+    }
+
+    pub fn delete(&self, ids: Vec<u64>) -> Result<()> {
+        store::delete_points(self.conn, &self.name, ids)
+    }
+
+    /// Applies `operations` in order and in one transaction: all of them,
+    /// or none if one fails.
+    pub fn apply(&self, operations: &[PointOperation]) -> Result<()> {
+        store::apply_batch(self.conn, &self.name, operations)
+    }
+
+    /// Checks `operations` as far as possible without applying them: that
+    /// the collection exists and that ids and vectors are valid for it.
+    pub fn check(&self, operations: &[PointOperation]) -> Result<()> {
+        store::check_write(self.conn, &self.name, operations)
+    }
+}
+
+#[test]
+fn test_vector_store() {
+    let store = VectorStore::open_in_memory().unwrap();
+    let collection = store.create_collection("test_vss", 4).unwrap();
+    let points = vec![
+        Point {
+            id: 1,
+            vector: vec![0.05, 0.61, 0.76, 0.74].into(),
+            payload: None,
+        },
+        Point {
+            id: 2,
+            vector: vec![0.18, 0.01, 0.85, 0.80].into(),
+            payload: None,
+        },
+    ];
+    assert_eq!(collection.upsert(&points).unwrap(), vec![1, 2]);
+    assert_eq!(collection.info().unwrap().points_count, 2);
+    assert_eq!(collection.get(2).unwrap().unwrap().vector, points[1].vector);
+    assert!(collection.get(3).unwrap().is_none());
+
+    let r = collection.search(&[0.2, 0.1, 0.9, 0.7], 1).unwrap();
+    assert_eq!(r[0].id, 2);
+
+    collection.delete(vec![1]).unwrap();
+    assert_eq!(
+        store
+            .collection("test_vss")
+            .unwrap()
+            .info()
+            .unwrap()
+            .points_count,
+        1
+    );
+    store.delete_collection("test_vss").unwrap();
+}